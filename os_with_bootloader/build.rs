@@ -20,17 +20,28 @@ fn main() {
 
     let kernel = PathBuf::from(std::env::var_os("CARGO_BIN_FILE_KERNEL_WITH_BOOTLOADER").unwrap());
 
+    // an optional file to load next to the kernel, e.g. a PSF font
+    println!("cargo:rerun-if-env-changed=KERNEL_RAMDISK");
+    let ramdisk = std::env::var_os("KERNEL_RAMDISK").map(PathBuf::from);
+    if let Some(ramdisk) = &ramdisk {
+        println!("cargo:rerun-if-changed={}", ramdisk.display());
+    }
+
     // create an UEFI disk image (optional)
     let uefi_path = out_dir.join("uefi.img");
-    bootloader::UefiBoot::new(&kernel)
-        .create_disk_image(&uefi_path)
-        .unwrap();
+    let mut uefi = bootloader::UefiBoot::new(&kernel);
+    if let Some(ramdisk) = &ramdisk {
+        uefi.set_ramdisk(ramdisk);
+    }
+    uefi.create_disk_image(&uefi_path).unwrap();
 
     // create a BIOS disk image
     let bios_path = out_dir.join("bios.img");
-    bootloader::BiosBoot::new(&kernel)
-        .create_disk_image(&bios_path)
-        .unwrap();
+    let mut bios = bootloader::BiosBoot::new(&kernel);
+    if let Some(ramdisk) = &ramdisk {
+        bios.set_ramdisk(ramdisk);
+    }
+    bios.create_disk_image(&bios_path).unwrap();
 
     // pass the disk image paths as env variables to the `main.rs`
     println!("cargo:rustc-env=UEFI_PATH={}", uefi_path.display());
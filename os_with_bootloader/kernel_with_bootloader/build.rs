@@ -0,0 +1,4 @@
+fn main() {
+    // log filters are compiled in, see src/logger.rs
+    println!("cargo:rerun-if-env-changed=KERNEL_LOG");
+}
@@ -0,0 +1,189 @@
+mod fadt;
+mod hpet;
+mod madt;
+mod mcfg;
+mod table;
+
+pub use fadt::Fadt;
+pub use hpet::Hpet;
+pub use madt::Madt;
+pub use mcfg::PciSegment;
+
+use alloc::vec::Vec;
+use spin::Once;
+use table::{Table, MAX_TABLE_SIZE, SDT_HEADER_SIZE};
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+const RSDP_V1_SIZE: usize = 20;
+const RSDP_V2_SIZE: usize = 36;
+
+#[derive(Debug)]
+pub enum AcpiError {
+    NoRsdp,
+    NoPhysicalMemoryMapping,
+    BadRsdpSignature,
+    BadRsdpChecksum,
+    //The RSDP or the RSDT/XSDT claims an impossible length.
+    BadTableLength,
+    BadRootTableChecksum,
+}
+
+//Everything the kernel learned from the firmware's ACPI tables.
+#[derive(Debug)]
+pub struct AcpiInfo {
+    pub revision: u8,
+    pub oem_id: [u8; 6],
+    pub madt: Option<Madt>,
+    pub fadt: Option<Fadt>,
+    pub hpet: Option<Hpet>,
+    pub pci_segments: Vec<PciSegment>,
+    pub skipped_tables: usize, // tables with a bad length or checksum
+}
+
+static ACPI_INFO: Once<AcpiInfo> = Once::new();
+
+//None before init() or when the firmware had no (valid) tables.
+pub fn info() -> Option<&'static AcpiInfo> {
+    ACPI_INFO.r#try()
+}
+
+//Tables are read through the bootloader's mapping of physical memory.
+fn phys_to_virt(physical_memory_offset: u64, phys: u64) -> u64 {
+    physical_memory_offset + phys
+}
+
+//Validate the RSDP and the RSDT/XSDT it points at, then parse the tables we
+//know. Needs the heap.
+pub fn init(
+    rsdp_addr: Option<u64>,
+    physical_memory_offset: Option<u64>,
+) -> Result<&'static AcpiInfo, AcpiError> {
+    let rsdp_addr = rsdp_addr.ok_or(AcpiError::NoRsdp)?;
+    let offset = physical_memory_offset.ok_or(AcpiError::NoPhysicalMemoryMapping)?;
+
+    let rsdp = unsafe { Table::new(phys_to_virt(offset, rsdp_addr), RSDP_V1_SIZE) };
+    if rsdp.bytes(0, 8) != Some(&RSDP_SIGNATURE[..]) {
+        return Err(AcpiError::BadRsdpSignature);
+    }
+    if !rsdp.checksum_ok() {
+        return Err(AcpiError::BadRsdpChecksum);
+    }
+    let revision = rsdp.u8(15).unwrap_or(0);
+    let mut oem_id = [0; 6];
+    oem_id.copy_from_slice(rsdp.bytes(9, 6).unwrap());
+
+    //ACPI 2.0+ has a 64-bit XSDT, covered by the extended checksum.
+    let (root, entry_size) = if revision >= 2 {
+        let length = unsafe { Table::new(phys_to_virt(offset, rsdp_addr), RSDP_V1_SIZE + 4) }
+            .u32(RSDP_V1_SIZE)
+            .unwrap_or(0) as usize;
+        if !(RSDP_V2_SIZE..=MAX_TABLE_SIZE).contains(&length) {
+            return Err(AcpiError::BadTableLength);
+        }
+        let rsdp = unsafe { Table::new(phys_to_virt(offset, rsdp_addr), length) };
+        if !rsdp.checksum_ok() {
+            return Err(AcpiError::BadRsdpChecksum);
+        }
+        (rsdp.u64(24).ok_or(AcpiError::BadRsdpChecksum)?, 8)
+    } else {
+        (rsdp.u32(16).unwrap_or(0) as u64, 4)
+    };
+
+    let root = unsafe { Table::from_header(phys_to_virt(offset, root)) }
+        .ok_or(AcpiError::BadTableLength)?;
+    if !root.checksum_ok() {
+        return Err(AcpiError::BadRootTableChecksum);
+    }
+
+    let mut info = AcpiInfo {
+        revision,
+        oem_id,
+        madt: None,
+        fadt: None,
+        hpet: None,
+        pci_segments: Vec::new(),
+        skipped_tables: 0,
+    };
+
+    let entries = (root.len() - SDT_HEADER_SIZE) / entry_size;
+    for i in 0..entries {
+        let entry_offset = SDT_HEADER_SIZE + i * entry_size;
+        let table_addr = match entry_size {
+            8 => root.u64(entry_offset),
+            _ => root.u32(entry_offset).map(u64::from),
+        };
+        let (addr, table) = match table_addr {
+            Some(addr) if addr != 0 => (addr, unsafe {
+                Table::from_header(phys_to_virt(offset, addr))
+            }),
+            _ => continue,
+        };
+        let table = match table {
+            Some(table) if table.checksum_ok() => table,
+            Some(table) => {
+                log::warn!(
+                    "ACPI table {} at {:#x}: bad checksum, skipped",
+                    core::str::from_utf8(&table.signature()).unwrap_or("????"),
+                    addr
+                );
+                info.skipped_tables += 1;
+                continue;
+            }
+            None => {
+                log::warn!("ACPI table at {:#x}: bad length, skipped", addr);
+                info.skipped_tables += 1;
+                continue;
+            }
+        };
+
+        match &table.signature() {
+            b"APIC" => info.madt = Madt::parse(table),
+            b"FACP" => info.fadt = Fadt::parse(table),
+            b"HPET" => info.hpet = Hpet::parse(table),
+            b"MCFG" => info.pci_segments = mcfg::parse(table),
+            _ => {}
+        }
+    }
+
+    Ok(ACPI_INFO.call_once(|| info))
+}
+
+pub fn log_summary(info: &AcpiInfo) {
+    log::info!(
+        "ACPI {}: OEM {}",
+        if info.revision >= 2 { "2.0+" } else { "1.0" },
+        core::str::from_utf8(&info.oem_id).unwrap_or("?").trim_end()
+    );
+    match &info.madt {
+        Some(madt) => log::info!(
+            "MADT: {} CPU(s) ({} enabled), {} I/O APIC(s), {} IRQ override(s), LAPIC at {:#x}",
+            madt.processors.len(),
+            madt.processors.iter().filter(|cpu| cpu.enabled).count(),
+            madt.io_apics.len(),
+            madt.overrides.len(),
+            madt.local_apic_address
+        ),
+        None => log::warn!("MADT: not found"),
+    }
+    match &info.fadt {
+        Some(fadt) => log::info!(
+            "FADT: SCI IRQ {}, PM timer port {:#x}, reset register: {}",
+            fadt.sci_interrupt,
+            fadt.pm_timer_block,
+            fadt.reset_register.is_some()
+        ),
+        None => log::info!("FADT: not found"),
+    }
+    match &info.hpet {
+        Some(hpet) => log::info!(
+            "HPET: at {:#x}, {} comparator(s)",
+            hpet.base_address,
+            hpet.comparators
+        ),
+        None => log::info!("HPET: not found"),
+    }
+    log::info!("MCFG: {} PCI segment(s)", info.pci_segments.len());
+    if info.skipped_tables > 0 {
+        log::warn!("{} table(s) skipped", info.skipped_tables);
+    }
+}
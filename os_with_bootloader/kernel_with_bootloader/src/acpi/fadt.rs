@@ -0,0 +1,61 @@
+use super::table::{GenericAddress, Table};
+
+//Fixed ACPI Description Table ("FACP"). Only the fields the kernel is likely
+//to need for power management and reset are pulled out.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub struct Fadt {
+    pub dsdt_address: u64,
+    pub sci_interrupt: u16,
+    pub smi_command_port: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub pm1a_control_block: u32,
+    pub pm1b_control_block: u32,
+    pub pm_timer_block: u32,
+    pub century_register: u8,
+    pub boot_architecture_flags: u16,
+    pub flags: u32,
+    pub reset_register: Option<GenericAddress>,
+    pub reset_value: u8,
+}
+
+//FADT flag: the reset register is supported.
+const FLAG_RESET_REG_SUP: u32 = 1 << 10;
+//IA-PC boot architecture flag: an 8042 keyboard controller exists.
+#[allow(dead_code)]
+const BOOT_ARCH_8042: u16 = 1 << 1;
+
+impl Fadt {
+    pub fn parse(table: Table) -> Option<Fadt> {
+        let flags = table.u32(112).unwrap_or(0);
+        let dsdt_address = match table.u64(140) {
+            Some(x_dsdt) if x_dsdt != 0 => x_dsdt,
+            _ => table.u32(40)? as u64,
+        };
+        Some(Fadt {
+            dsdt_address,
+            sci_interrupt: table.u16(46)?,
+            smi_command_port: table.u32(48)?,
+            acpi_enable: table.u8(52)?,
+            acpi_disable: table.u8(53)?,
+            pm1a_control_block: table.u32(64)?,
+            pm1b_control_block: table.u32(68)?,
+            pm_timer_block: table.u32(76)?,
+            // the fields below are ACPI 2.0+, zero on older firmware
+            century_register: table.u8(108).unwrap_or(0),
+            boot_architecture_flags: table.u16(109).unwrap_or(0),
+            flags,
+            reset_register: table
+                .generic_address(116)
+                .filter(|_| flags & FLAG_RESET_REG_SUP != 0),
+            reset_value: table.u8(128).unwrap_or(0),
+        })
+    }
+
+    //Old firmware leaves the flags at zero, so no flag does not mean no 8042.
+    #[allow(dead_code)]
+    pub fn has_8042(&self) -> bool {
+        self.boot_architecture_flags == 0 || self.boot_architecture_flags & BOOT_ARCH_8042 != 0
+    }
+}
@@ -0,0 +1,29 @@
+use super::table::{Table, SDT_HEADER_SIZE};
+
+//High Precision Event Timer table ("HPET")
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub struct Hpet {
+    pub base_address: u64,
+    pub hpet_number: u8,
+    pub minimum_tick: u16,
+    pub comparators: u8,
+    pub counter_64bit: bool,
+    pub legacy_replacement: bool,
+    pub pci_vendor_id: u16,
+}
+
+impl Hpet {
+    pub fn parse(table: Table) -> Option<Hpet> {
+        let block_id = table.u32(SDT_HEADER_SIZE)?;
+        Some(Hpet {
+            base_address: table.generic_address(SDT_HEADER_SIZE + 4)?.address,
+            hpet_number: table.u8(SDT_HEADER_SIZE + 16)?,
+            minimum_tick: table.u16(SDT_HEADER_SIZE + 17)?,
+            comparators: ((block_id >> 8) & 0x1f) as u8 + 1,
+            counter_64bit: block_id & (1 << 13) != 0,
+            legacy_replacement: block_id & (1 << 15) != 0,
+            pci_vendor_id: (block_id >> 16) as u16,
+        })
+    }
+}
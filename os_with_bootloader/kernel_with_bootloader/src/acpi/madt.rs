@@ -0,0 +1,134 @@
+use super::table::{Table, SDT_HEADER_SIZE};
+use alloc::vec::Vec;
+
+const ENTRY_PROCESSOR_LOCAL_APIC: u8 = 0;
+const ENTRY_IO_APIC: u8 = 1;
+const ENTRY_INTERRUPT_SOURCE_OVERRIDE: u8 = 2;
+const ENTRY_LOCAL_APIC_NMI: u8 = 4;
+const ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+
+//MPS INTI flags used by overrides and NMI entries.
+const POLARITY_MASK: u16 = 0b11;
+const POLARITY_ACTIVE_LOW: u16 = 0b11;
+const TRIGGER_MASK: u16 = 0b1100;
+const TRIGGER_LEVEL: u16 = 0b1100;
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub struct Processor {
+    pub processor_id: u8,
+    pub apic_id: u8,
+    pub enabled: bool,
+    pub online_capable: bool,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub struct IoApic {
+    pub id: u8,
+    pub address: u32,
+    pub gsi_base: u32,
+}
+
+//ISA IRQ `source` is wired to global system interrupt `gsi` instead of the
+//identity mapping.
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptOverride {
+    pub bus: u8,
+    pub source: u8,
+    pub gsi: u32,
+    pub flags: u16,
+}
+
+impl InterruptOverride {
+    pub fn active_low(&self) -> bool {
+        self.flags & POLARITY_MASK == POLARITY_ACTIVE_LOW
+    }
+
+    pub fn level_triggered(&self) -> bool {
+        self.flags & TRIGGER_MASK == TRIGGER_LEVEL
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub struct LocalApicNmi {
+    pub processor_id: u8, // 0xff means all processors
+    pub flags: u16,
+    pub lint: u8,
+}
+
+//Multiple APIC Description Table ("APIC")
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct Madt {
+    pub local_apic_address: u64,
+    pub pcat_compat: bool, // legacy 8259 PICs are present
+    pub processors: Vec<Processor>,
+    pub io_apics: Vec<IoApic>,
+    pub overrides: Vec<InterruptOverride>,
+    pub nmis: Vec<LocalApicNmi>,
+}
+
+impl Madt {
+    pub fn parse(table: Table) -> Option<Madt> {
+        let mut madt = Madt {
+            local_apic_address: table.u32(SDT_HEADER_SIZE)? as u64,
+            pcat_compat: table.u32(SDT_HEADER_SIZE + 4)? & 1 != 0,
+            processors: Vec::new(),
+            io_apics: Vec::new(),
+            overrides: Vec::new(),
+            nmis: Vec::new(),
+        };
+
+        let mut offset = SDT_HEADER_SIZE + 8;
+        while offset + 2 <= table.len() {
+            let entry_type = table.u8(offset)?;
+            let length = table.u8(offset + 1)? as usize;
+            if length < 2 {
+                break; // malformed, would loop forever
+            }
+            match entry_type {
+                ENTRY_PROCESSOR_LOCAL_APIC => {
+                    let flags = table.u32(offset + 4)?;
+                    madt.processors.push(Processor {
+                        processor_id: table.u8(offset + 2)?,
+                        apic_id: table.u8(offset + 3)?,
+                        enabled: flags & 1 != 0,
+                        online_capable: flags & 2 != 0,
+                    });
+                }
+                ENTRY_IO_APIC => madt.io_apics.push(IoApic {
+                    id: table.u8(offset + 2)?,
+                    address: table.u32(offset + 4)?,
+                    gsi_base: table.u32(offset + 8)?,
+                }),
+                ENTRY_INTERRUPT_SOURCE_OVERRIDE => madt.overrides.push(InterruptOverride {
+                    bus: table.u8(offset + 2)?,
+                    source: table.u8(offset + 3)?,
+                    gsi: table.u32(offset + 4)?,
+                    flags: table.u16(offset + 8)?,
+                }),
+                ENTRY_LOCAL_APIC_NMI => madt.nmis.push(LocalApicNmi {
+                    processor_id: table.u8(offset + 2)?,
+                    flags: table.u16(offset + 3)?,
+                    lint: table.u8(offset + 5)?,
+                }),
+                ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE => {
+                    madt.local_apic_address = table.u64(offset + 4)?;
+                }
+                _ => {} // x2APIC and friends, not used yet
+            }
+            offset += length;
+        }
+
+        Some(madt)
+    }
+
+    //The override for ISA IRQ `irq`, if the firmware moved it.
+    pub fn interrupt_override(&self, irq: u8) -> Option<&InterruptOverride> {
+        self.overrides
+            .iter()
+            .find(|entry| entry.bus == 0 && entry.source == irq)
+    }
+}
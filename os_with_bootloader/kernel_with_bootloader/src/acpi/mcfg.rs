@@ -0,0 +1,37 @@
+use super::table::{Table, SDT_HEADER_SIZE};
+use alloc::vec::Vec;
+
+const ENTRIES_OFFSET: usize = SDT_HEADER_SIZE + 8;
+const ENTRY_SIZE: usize = 16;
+
+//One PCI Express enhanced configuration space window from the "MCFG" table.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub struct PciSegment {
+    pub base_address: u64,
+    pub segment_group: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+pub fn parse(table: Table) -> Vec<PciSegment> {
+    let mut segments = Vec::new();
+    let mut offset = ENTRIES_OFFSET;
+    while offset + ENTRY_SIZE <= table.len() {
+        if let (Some(base_address), Some(segment_group), Some(start_bus), Some(end_bus)) = (
+            table.u64(offset),
+            table.u16(offset + 8),
+            table.u8(offset + 10),
+            table.u8(offset + 11),
+        ) {
+            segments.push(PciSegment {
+                base_address,
+                segment_group,
+                start_bus,
+                end_bus,
+            });
+        }
+        offset += ENTRY_SIZE;
+    }
+    segments
+}
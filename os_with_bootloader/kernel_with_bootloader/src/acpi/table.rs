@@ -0,0 +1,135 @@
+use core::ptr;
+use core::slice;
+
+pub const SDT_HEADER_SIZE: usize = 36;
+//Far more than any table we parse needs. A larger length is firmware garbage
+//and would have us read past the end of physical memory.
+pub const MAX_TABLE_SIZE: usize = 1 << 20;
+
+//A checksummed ACPI table (or the RSDP) viewed as raw bytes. ACPI structures
+//are packed and the tables can sit at any alignment, so fields are read by
+//offset. Reads past the end of the table give None, which is how fields that
+//only newer revisions have are handled.
+#[derive(Clone, Copy)]
+pub struct Table {
+    bytes: &'static [u8],
+}
+
+impl Table {
+    //Safety: `virt..virt + len` has to be mapped and must stay mapped.
+    pub unsafe fn new(virt: u64, len: usize) -> Table {
+        Table {
+            bytes: slice::from_raw_parts(virt as *const u8, len),
+        }
+    }
+
+    //Safety: `virt` must point at a mapped SDT header. None if the length in
+    //the header can't be right.
+    pub unsafe fn from_header(virt: u64) -> Option<Table> {
+        let length = ptr::read_unaligned((virt + 4) as *const u32) as usize;
+        if !(SDT_HEADER_SIZE..=MAX_TABLE_SIZE).contains(&length) {
+            return None;
+        }
+        Some(Table::new(virt, length))
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn checksum_ok(&self) -> bool {
+        self.bytes
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+            == 0
+    }
+
+    //Zeros for a table too short to have one.
+    pub fn signature(&self) -> [u8; 4] {
+        let mut signature = [0; 4];
+        if let Some(bytes) = self.bytes(0, 4) {
+            signature.copy_from_slice(bytes);
+        }
+        signature
+    }
+
+    pub fn bytes(&self, offset: usize, len: usize) -> Option<&'static [u8]> {
+        self.bytes.get(offset..offset + len)
+    }
+
+    pub fn u8(&self, offset: usize) -> Option<u8> {
+        self.bytes.get(offset).copied()
+    }
+
+    pub fn u16(&self, offset: usize) -> Option<u16> {
+        self.bytes(offset, 2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn u32(&self, offset: usize) -> Option<u32> {
+        let mut value = [0; 4];
+        value.copy_from_slice(self.bytes(offset, 4)?);
+        Some(u32::from_le_bytes(value))
+    }
+
+    pub fn u64(&self, offset: usize) -> Option<u64> {
+        let mut value = [0; 8];
+        value.copy_from_slice(self.bytes(offset, 8)?);
+        Some(u64::from_le_bytes(value))
+    }
+
+    //12 byte Generic Address Structure
+    pub fn generic_address(&self, offset: usize) -> Option<GenericAddress> {
+        Some(GenericAddress {
+            address_space: self.u8(offset)?,
+            bit_width: self.u8(offset + 1)?,
+            bit_offset: self.u8(offset + 2)?,
+            access_size: self.u8(offset + 3)?,
+            address: self.u64(offset + 4)?,
+        })
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub struct GenericAddress {
+    pub address_space: u8, // 0 = memory, 1 = I/O port
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    //An SDT header claiming `length` bytes, in a buffer of SDT_HEADER_SIZE.
+    fn header(length: u32) -> alloc::vec::Vec<u8> {
+        let mut bytes = vec![0u8; SDT_HEADER_SIZE];
+        bytes[..4].copy_from_slice(b"TEST");
+        bytes[4..8].copy_from_slice(&length.to_le_bytes());
+        bytes
+    }
+
+    #[test_case]
+    fn table_lengths_are_checked() {
+        for length in [0, 4, SDT_HEADER_SIZE as u32 - 1, MAX_TABLE_SIZE as u32 + 1] {
+            let bytes = header(length);
+            assert!(unsafe { Table::from_header(bytes.as_ptr() as u64) }.is_none());
+        }
+        let bytes = header(SDT_HEADER_SIZE as u32);
+        let table = unsafe { Table::from_header(bytes.as_ptr() as u64) }.unwrap();
+        assert_eq!(table.len(), SDT_HEADER_SIZE);
+        assert_eq!(&table.signature(), b"TEST");
+    }
+
+    #[test_case]
+    fn short_tables_have_no_signature() {
+        let bytes = [b'A', b'B'];
+        let table = unsafe { Table::new(bytes.as_ptr() as u64, bytes.len()) };
+        assert_eq!(table.signature(), [0; 4]);
+        assert_eq!(table.u32(0), None);
+    }
+}
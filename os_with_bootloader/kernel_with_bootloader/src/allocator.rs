@@ -0,0 +1,106 @@
+use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
+use core::alloc::Layout;
+use core::ops::Range;
+use good_memory_allocator::SpinLockedAllocator;
+
+//The framebuffer writer keeps a copy of the screen here, a few MiB.
+pub const HEAP_SIZE: usize = 16 * 1024 * 1024; // 16 MiB
+
+//Anything below 1 MiB is left alone, the BIOS and legacy devices live there.
+const LOW_MEMORY_END: u64 = 0x10_0000;
+const PAGE_SIZE: u64 = 4096;
+
+#[global_allocator]
+static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::empty();
+
+#[derive(Debug, Clone, Copy)]
+pub enum HeapError {
+    NoPhysicalMemoryOffset,
+    NoUsableRegion,
+}
+
+//Physical frames handed to the heap, kept so that nothing else reuses them.
+static mut HEAP_PHYS_RANGE: Option<Range<u64>> = None;
+
+pub fn heap_phys_range() -> Option<Range<u64>> {
+    unsafe { HEAP_PHYS_RANGE.clone() }
+}
+
+//Pick the first usable region that can hold the whole heap.
+fn find_heap_region(memory_regions: &MemoryRegions) -> Option<Range<u64>> {
+    memory_regions
+        .iter()
+        .filter(|region| region.kind == MemoryRegionKind::Usable)
+        .map(|region| {
+            let start = align_up(region.start.max(LOW_MEMORY_END), PAGE_SIZE);
+            start..region.end
+        })
+        .find(|range| range.end > range.start && range.end - range.start >= HEAP_SIZE as u64)
+        .map(|range| range.start..range.start + HEAP_SIZE as u64)
+}
+
+fn align_up(addr: u64, align: u64) -> u64 {
+    (addr + align - 1) & !(align - 1)
+}
+
+//The bootloader maps the complete physical memory at `physical_memory_offset`
+//(see `Mapping::Dynamic` in BOOTLOADER_CONFIG), so the heap region is already
+//reachable through that window and we only have to hand its virtual address
+//to the allocator.
+pub fn init_heap(
+    memory_regions: &MemoryRegions,
+    physical_memory_offset: Option<u64>,
+) -> Result<Range<u64>, HeapError> {
+    let offset = physical_memory_offset.ok_or(HeapError::NoPhysicalMemoryOffset)?;
+    let phys_range = find_heap_region(memory_regions).ok_or(HeapError::NoUsableRegion)?;
+    let heap_start = offset + phys_range.start;
+
+    unsafe {
+        ALLOCATOR.init(heap_start as usize, HEAP_SIZE);
+        HEAP_PHYS_RANGE = Some(phys_range);
+    }
+
+    Ok(heap_start..heap_start + HEAP_SIZE as u64)
+}
+
+#[alloc_error_handler]
+fn alloc_error_handler(layout: Layout) -> ! {
+    log::error!(
+        "could not allocate {} bytes (align {}) from the {} KiB kernel heap",
+        layout.size(),
+        layout.align(),
+        HEAP_SIZE / 1024
+    );
+    panic!("out of kernel heap memory");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HEAP_SIZE;
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+
+    #[test_case]
+    fn simple_allocation() {
+        let first = Box::new(41);
+        let second = Box::new(13);
+        assert_eq!(*first, 41);
+        assert_eq!(*second, 13);
+    }
+
+    #[test_case]
+    fn large_vec() {
+        let n = 1000;
+        let vec: Vec<u64> = (0..n).collect();
+        assert_eq!(vec.iter().sum::<u64>(), (n - 1) * n / 2);
+    }
+
+    //Allocates twice the heap size in total, so freed memory has to be reused.
+    #[test_case]
+    fn many_boxes() {
+        for i in 0..2 * HEAP_SIZE / 4096 {
+            let page = Box::new([i as u8; 4096]);
+            assert_eq!(page[4095], i as u8);
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use lazy_static::lazy_static;
+use x86_64::instructions::segmentation::{Segment, CS, DS, ES, SS};
+use x86_64::instructions::tables::load_tss;
+use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
+use x86_64::structures::tss::TaskStateSegment;
+use x86_64::VirtAddr;
+
+//Interrupt stack table slots. Each handler registered with one of these gets
+//a known good stack, even when the kernel stack itself is what overflowed.
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub const NMI_IST_INDEX: u16 = 1;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
+
+const IST_STACK_SIZE: usize = 4096 * 5;
+
+//Statics instead of heap allocations, so the stacks exist before the heap does.
+static mut DOUBLE_FAULT_STACK: [u8; IST_STACK_SIZE] = [0; IST_STACK_SIZE];
+static mut NMI_STACK: [u8; IST_STACK_SIZE] = [0; IST_STACK_SIZE];
+static mut MACHINE_CHECK_STACK: [u8; IST_STACK_SIZE] = [0; IST_STACK_SIZE];
+
+//Stacks grow downwards, so an IST entry points at the end of its stack.
+fn stack_end(stack: *const [u8; IST_STACK_SIZE]) -> VirtAddr {
+    VirtAddr::from_ptr(stack) + IST_STACK_SIZE
+}
+
+lazy_static! {
+    static ref TSS: TaskStateSegment = {
+        let mut tss = TaskStateSegment::new();
+        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+            stack_end(unsafe { core::ptr::addr_of!(DOUBLE_FAULT_STACK) });
+        tss.interrupt_stack_table[NMI_IST_INDEX as usize] =
+            stack_end(unsafe { core::ptr::addr_of!(NMI_STACK) });
+        tss.interrupt_stack_table[MACHINE_CHECK_IST_INDEX as usize] =
+            stack_end(unsafe { core::ptr::addr_of!(MACHINE_CHECK_STACK) });
+        tss
+    };
+}
+
+struct Selectors {
+    code_selector: SegmentSelector,
+    data_selector: SegmentSelector,
+    tss_selector: SegmentSelector,
+}
+
+lazy_static! {
+    static ref GDT: (GlobalDescriptorTable, Selectors) = {
+        let mut gdt = GlobalDescriptorTable::new();
+        let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
+        let data_selector = gdt.add_entry(Descriptor::kernel_data_segment());
+        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        (
+            gdt,
+            Selectors {
+                code_selector,
+                data_selector,
+                tss_selector,
+            },
+        )
+    };
+}
+
+//Replace the bootloader's GDT with ours and load the TSS. Has to run before
+//the IDT is loaded, since the IDT entries refer to the IST slots above.
+pub fn init() {
+    GDT.0.load();
+    unsafe {
+        CS::set_reg(GDT.1.code_selector);
+        DS::set_reg(GDT.1.data_selector);
+        ES::set_reg(GDT.1.data_selector);
+        SS::set_reg(GDT.1.data_selector);
+        load_tss(GDT.1.tss_selector);
+    }
+}
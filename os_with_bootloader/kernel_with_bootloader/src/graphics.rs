@@ -0,0 +1,252 @@
+//Drawing on a framebuffer-like buffer described by a FrameBufferInfo: pixels,
+//shapes and bitmaps, all clipped. Canvas also implements embedded-graphics'
+//DrawTarget, see graphics/embedded.rs.
+
+mod embedded;
+mod shapes;
+
+use crate::writer::{DirtyRegion, Rect};
+use bootloader_api::info::{FrameBufferInfo, PixelFormat};
+
+//How `color` ([r, g, b, a], alpha unused) is stored in a pixel of
+//`pixel_format`. Only the first bytes_per_pixel bytes are meant.
+pub fn pixel_bytes(pixel_format: PixelFormat, color: [u8; 4]) -> Option<[u8; 4]> {
+    let [r, g, b, _] = color;
+    match pixel_format {
+        PixelFormat::Rgb => Some([r, g, b, 0]),
+        PixelFormat::Bgr => Some([b, g, r, 0]),
+        //grayscale, by the luma weights of BT.601
+        PixelFormat::U8 => Some([
+            ((r as u32 * 77 + g as u32 * 150 + b as u32 * 29) >> 8) as u8,
+            0,
+            0,
+            0,
+        ]),
+        _ => None,
+    }
+}
+
+pub struct Canvas<'a> {
+    buffer: &'a mut [u8],
+    info: FrameBufferInfo,
+    //Nothing outside is drawn.
+    clip: Rect,
+    //Bounds of what was drawn, as (left, top, right, bottom), added to
+    //`dirty` when the canvas is dropped.
+    touched: Option<(usize, usize, usize, usize)>,
+    dirty: Option<&'a mut DirtyRegion>,
+}
+
+impl<'a> Canvas<'a> {
+    pub fn new(buffer: &'a mut [u8], info: FrameBufferInfo) -> Self {
+        Canvas {
+            buffer,
+            info,
+            clip: Rect::new(0, 0, info.width, info.height),
+            touched: None,
+            dirty: None,
+        }
+    }
+
+    //Report what gets drawn to `dirty`, for buffers that are flushed.
+    pub fn track(mut self, dirty: &'a mut DirtyRegion) -> Self {
+        self.dirty = Some(dirty);
+        self
+    }
+
+    pub fn width(&self) -> usize {
+        self.info.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.info.height
+    }
+
+    //Limit drawing to `clip`, within the canvas.
+    pub fn set_clip(&mut self, clip: Rect) {
+        let x = clip.x.min(self.info.width);
+        let y = clip.y.min(self.info.height);
+        self.clip = Rect::new(
+            x,
+            y,
+            clip.right().min(self.info.width) - x,
+            clip.bottom().min(self.info.height) - y,
+        );
+    }
+
+    pub fn clip(&self) -> Rect {
+        self.clip
+    }
+
+    fn encode(&self, color: [u8; 4]) -> [u8; 4] {
+        match pixel_bytes(self.info.pixel_format, color) {
+            Some(bytes) => bytes,
+            None => panic!(
+                "pixel format {:?} not supported in graphics",
+                self.info.pixel_format
+            ),
+        }
+    }
+
+    //Columns `left..right` of row `y` clipped, None if nothing is left.
+    fn clip_span(&self, y: i32, left: i32, right: i32) -> Option<(usize, usize, usize)> {
+        let clip = self.clip;
+        if y < clip.y as i32 || y >= clip.bottom() as i32 {
+            return None;
+        }
+        let left = left.max(clip.x as i32);
+        let right = right.min(clip.right() as i32);
+        match left < right {
+            true => Some((y as usize, left as usize, right as usize)),
+            false => None,
+        }
+    }
+
+    fn touch(&mut self, y: usize, left: usize, right: usize) {
+        self.touched = Some(match self.touched {
+            None => (left, y, right, y + 1),
+            Some((l, t, r, b)) => (l.min(left), t.min(y), r.max(right), b.max(y + 1)),
+        });
+    }
+
+    //Set the pixels `left..right` of row `y` to already encoded `bytes`.
+    fn span(&mut self, y: i32, left: i32, right: i32, bytes: [u8; 4]) {
+        if let Some((y, left, right)) = self.clip_span(y, left, right) {
+            let bytes_per_pixel = self.info.bytes_per_pixel;
+            let start = (y * self.info.stride + left) * bytes_per_pixel;
+            let end = (y * self.info.stride + right) * bytes_per_pixel;
+            for pixel in self.buffer[start..end].chunks_exact_mut(bytes_per_pixel) {
+                pixel.copy_from_slice(&bytes[..bytes_per_pixel]);
+            }
+            self.touch(y, left, right);
+        }
+    }
+
+    pub fn pixel(&mut self, x: i32, y: i32, color: [u8; 4]) {
+        let bytes = self.encode(color);
+        self.span(y, x, x.saturating_add(1), bytes);
+    }
+
+    pub fn fill_rect(&mut self, x: i32, y: i32, width: i32, height: i32, color: [u8; 4]) {
+        let bytes = self.encode(color);
+        for y in y..y.saturating_add(height) {
+            self.span(y, x, x.saturating_add(width), bytes);
+        }
+    }
+
+    pub fn clear(&mut self, color: [u8; 4]) {
+        self.fill_rect(0, 0, self.info.width as i32, self.info.height as i32, color);
+    }
+
+    //Copy a `width` wide bitmap of [r, g, b, a] pixels, row by row, with its
+    //top left corner at (x, y).
+    pub fn blit(&mut self, x: i32, y: i32, width: usize, pixels: &[[u8; 4]]) {
+        if width == 0 {
+            return;
+        }
+        for (row, line) in pixels.chunks(width).enumerate() {
+            let row_y = y.saturating_add(row as i32);
+            for (column, color) in line.iter().enumerate() {
+                let bytes = self.encode(*color);
+                let column_x = x.saturating_add(column as i32);
+                self.span(row_y, column_x, column_x.saturating_add(1), bytes);
+            }
+        }
+    }
+}
+
+impl Drop for Canvas<'_> {
+    fn drop(&mut self) {
+        if let (Some(dirty), Some((left, top, right, bottom))) = (&mut self.dirty, self.touched) {
+            dirty.add(Rect::new(left, top, right - left, bottom - top));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    pub const SIZE: usize = 16;
+    pub const WHITE: [u8; 4] = [255, 255, 255, 255];
+
+    pub fn test_info() -> FrameBufferInfo {
+        FrameBufferInfo {
+            byte_len: SIZE * SIZE * 3,
+            width: SIZE,
+            height: SIZE,
+            pixel_format: PixelFormat::Rgb,
+            bytes_per_pixel: 3,
+            stride: SIZE,
+        }
+    }
+
+    pub fn test_buffer() -> Vec<u8> {
+        vec![0; SIZE * SIZE * 3]
+    }
+
+    //The (x, y) of every pixel that is not black.
+    pub fn lit(buffer: &[u8]) -> Vec<(usize, usize)> {
+        buffer
+            .chunks_exact(3)
+            .enumerate()
+            .filter(|(_, pixel)| pixel.iter().any(|byte| *byte != 0))
+            .map(|(index, _)| (index % SIZE, index / SIZE))
+            .collect()
+    }
+
+    #[test_case]
+    fn pixels_are_encoded_per_format() {
+        let color = [1, 2, 3, 4];
+        assert_eq!(pixel_bytes(PixelFormat::Rgb, color), Some([1, 2, 3, 0]));
+        assert_eq!(pixel_bytes(PixelFormat::Bgr, color), Some([3, 2, 1, 0]));
+        assert_eq!(pixel_bytes(PixelFormat::U8, WHITE), Some([255, 0, 0, 0]));
+    }
+
+    #[test_case]
+    fn drawing_is_clipped() {
+        let mut buffer = test_buffer();
+        let mut canvas = Canvas::new(&mut buffer, test_info());
+        canvas.fill_rect(-4, -4, 6, 6, WHITE);
+        canvas.set_clip(Rect::new(10, 10, 100, 100));
+        canvas.pixel(5, 5, WHITE);
+        canvas.fill_rect(14, 14, 10, 10, WHITE);
+        assert_eq!(canvas.clip(), Rect::new(10, 10, 6, 6));
+        drop(canvas);
+        assert_eq!(
+            lit(&buffer),
+            [
+                (0, 0),
+                (1, 0),
+                (0, 1),
+                (1, 1),
+                (14, 14),
+                (15, 14),
+                (14, 15),
+                (15, 15)
+            ]
+        );
+    }
+
+    #[test_case]
+    fn blit_copies_rows() {
+        let mut buffer = test_buffer();
+        let red = [255, 0, 0, 255];
+        Canvas::new(&mut buffer, test_info()).blit(15, 0, 2, &[red, WHITE, WHITE, red]);
+        assert_eq!(lit(&buffer), [(15, 0), (15, 1)]);
+        assert_eq!(buffer[15 * 3..16 * 3], [255, 0, 0]);
+    }
+
+    #[test_case]
+    fn drawn_area_is_marked_dirty() {
+        let mut buffer = test_buffer();
+        let mut dirty = DirtyRegion::new();
+        let mut canvas = Canvas::new(&mut buffer, test_info()).track(&mut dirty);
+        canvas.pixel(2, 3, WHITE);
+        canvas.pixel(5, 1, WHITE);
+        drop(canvas);
+        assert_eq!(dirty.rects(), [Rect::new(2, 1, 4, 3)]);
+    }
+}
@@ -0,0 +1,72 @@
+//Lets embedded-graphics widgets, fonts and shapes draw on a Canvas.
+use super::Canvas;
+use core::convert::Infallible;
+use embedded_graphics_core::draw_target::DrawTarget;
+use embedded_graphics_core::geometry::{OriginDimensions, Size};
+use embedded_graphics_core::pixelcolor::{Rgb888, RgbColor};
+use embedded_graphics_core::primitives::Rectangle;
+use embedded_graphics_core::Pixel;
+
+fn rgba(color: Rgb888) -> [u8; 4] {
+    [color.r(), color.g(), color.b(), 255]
+}
+
+impl OriginDimensions for Canvas<'_> {
+    fn size(&self) -> Size {
+        Size::new(self.width() as u32, self.height() as u32)
+    }
+}
+
+impl DrawTarget for Canvas<'_> {
+    type Color = Rgb888;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            self.pixel(point.x, point.y, rgba(color));
+        }
+        Ok(())
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        self.fill_rect(
+            area.top_left.x,
+            area.top_left.y,
+            area.size.width.min(i32::MAX as u32) as i32,
+            area.size.height.min(i32::MAX as u32) as i32,
+            rgba(color),
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{lit, test_buffer, test_info};
+    use super::*;
+    use embedded_graphics_core::geometry::{Dimensions, Point};
+
+    #[test_case]
+    fn draws_through_draw_target() {
+        let mut buffer = test_buffer();
+        let mut canvas = Canvas::new(&mut buffer, test_info());
+        assert_eq!(canvas.bounding_box().size, Size::new(16, 16));
+        let pixels = [
+            Pixel(Point::new(1, 2), Rgb888::new(10, 20, 30)),
+            Pixel(Point::new(-1, 2), Rgb888::WHITE),
+        ];
+        canvas.draw_iter(pixels).unwrap();
+        canvas
+            .fill_solid(
+                &Rectangle::new(Point::new(15, 15), Size::new(4, 4)),
+                Rgb888::WHITE,
+            )
+            .unwrap();
+        drop(canvas);
+        assert_eq!(lit(&buffer), [(1, 2), (15, 15)]);
+        assert_eq!(buffer[(2 * 16 + 1) * 3..(2 * 16 + 2) * 3], [10, 20, 30]);
+    }
+}
@@ -0,0 +1,329 @@
+//Lines and shapes, built on Canvas::span so they are clipped like the rest.
+use super::Canvas;
+use alloc::vec::Vec;
+
+//Cohen–Sutherland outcodes, where a point lies relative to the clip
+const LEFT: u8 = 1;
+const RIGHT: u8 = 2;
+const ABOVE: u8 = 4;
+const BELOW: u8 = 8;
+
+impl Canvas<'_> {
+    //Bresenham's line, both ends included. It is clipped first, so endpoints
+    //far off the canvas cost nothing.
+    pub fn line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, color: [u8; 4]) {
+        let (x0, y0, x1, y1) = match self.clip_line(x0, y0, x1, y1) {
+            Some(line) => line,
+            None => return,
+        };
+        let bytes = self.encode(color);
+        let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+        let (step_x, step_y) = ((x1 - x0).signum(), (y1 - y0).signum());
+        let (mut x, mut y) = (x0, y0);
+        let mut error = dx + dy;
+        loop {
+            //within the clip, so both fit an i32
+            self.span(y as i32, x as i32, x as i32 + 1, bytes);
+            if x == x1 && y == y1 {
+                break;
+            }
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    //The part of the line from (x0, y0) to (x1, y1) inside the clip, by
+    //Cohen–Sutherland, None if nothing is.
+    fn clip_line(&self, x0: i32, y0: i32, x1: i32, y1: i32) -> Option<(i64, i64, i64, i64)> {
+        if self.clip.width == 0 || self.clip.height == 0 {
+            return None;
+        }
+        //inclusive bounds
+        let (left, top) = (self.clip.x as i64, self.clip.y as i64);
+        let (right, bottom) = (self.clip.right() as i64 - 1, self.clip.bottom() as i64 - 1);
+        let outcode = |x: i64, y: i64| {
+            let mut code = 0;
+            if x < left {
+                code |= LEFT;
+            } else if x > right {
+                code |= RIGHT;
+            }
+            if y < top {
+                code |= ABOVE;
+            } else if y > bottom {
+                code |= BELOW;
+            }
+            code
+        };
+        //where the line crosses `x` or `y`, products of two i32 spans need i128
+        let at = |from: i64, to: i64, along_from: i64, along_to: i64, along: i64| {
+            let offset = (to - from) as i128 * (along - along_from) as i128
+                / (along_to - along_from) as i128;
+            from + offset as i64
+        };
+
+        let (mut x0, mut y0, mut x1, mut y1) = (x0 as i64, y0 as i64, x1 as i64, y1 as i64);
+        let (mut code0, mut code1) = (outcode(x0, y0), outcode(x1, y1));
+        loop {
+            if code0 | code1 == 0 {
+                return Some((x0, y0, x1, y1));
+            }
+            if code0 & code1 != 0 {
+                return None; // both on the same outer side
+            }
+            //move an endpoint that is outside onto the edge it is beyond
+            let code = if code0 != 0 { code0 } else { code1 };
+            let (x, y) = if code & ABOVE != 0 {
+                (at(x0, x1, y0, y1, top), top)
+            } else if code & BELOW != 0 {
+                (at(x0, x1, y0, y1, bottom), bottom)
+            } else if code & LEFT != 0 {
+                (left, at(y0, y1, x0, x1, left))
+            } else {
+                (right, at(y0, y1, x0, x1, right))
+            };
+            if code0 != 0 {
+                (x0, y0, code0) = (x, y, outcode(x, y));
+            } else {
+                (x1, y1, code1) = (x, y, outcode(x, y));
+            }
+        }
+    }
+
+    //Outline of the `width` by `height` rectangle at (x, y).
+    pub fn rect(&mut self, x: i32, y: i32, width: i32, height: i32, color: [u8; 4]) {
+        if width <= 0 || height <= 0 {
+            return;
+        }
+        //an edge that doesn't fit an i32 is far off the canvas anyway
+        let (right, bottom) = (x.saturating_add(width - 1), y.saturating_add(height - 1));
+        self.line(x, y, right, y, color);
+        self.line(x, bottom, right, bottom, color);
+        self.line(x, y, x, bottom, color);
+        self.line(right, y, right, bottom, color);
+    }
+
+    pub fn circle(&mut self, center_x: i32, center_y: i32, radius: i32, color: [u8; 4]) {
+        self.ellipse(center_x, center_y, radius, radius, color);
+    }
+
+    //Midpoint ellipse with radii `radius_x` and `radius_y`.
+    pub fn ellipse(
+        &mut self,
+        center_x: i32,
+        center_y: i32,
+        radius_x: i32,
+        radius_y: i32,
+        color: [u8; 4],
+    ) {
+        if radius_x < 0 || radius_y < 0 {
+            return;
+        }
+        let clip = self.clip;
+        let (center_x, center_y) = (center_x as i64, center_y as i64);
+        if center_x + (radius_x as i64) < clip.x as i64
+            || center_x - (radius_x as i64) >= clip.right() as i64
+            || center_y + (radius_y as i64) < clip.y as i64
+            || center_y - (radius_y as i64) >= clip.bottom() as i64
+        {
+            return;
+        }
+        let bytes = self.encode(color);
+        let plot = |canvas: &mut Self, x: i128, y: i128| {
+            let (x, y) = (x as i64, y as i64);
+            for (px, py) in [(x, y), (-x, y), (x, -y), (-x, -y)] {
+                //off the canvas when it doesn't fit an i32
+                let clamp = |value: i64| value.clamp(i32::MIN as i64, i32::MAX as i64) as i32;
+                let px = clamp(center_x + px);
+                canvas.span(clamp(center_y + py), px, px.saturating_add(1), bytes);
+            }
+        };
+        //the decision terms grow with the fourth power of the radii
+        let (rx2, ry2) = (
+            radius_x as i128 * radius_x as i128,
+            radius_y as i128 * radius_y as i128,
+        );
+        let (mut x, mut y) = (0i128, radius_y as i128);
+        let (mut px, mut py) = (0, 2 * rx2 * y);
+
+        //where the slope is above -1, step x
+        let mut p = ry2 - rx2 * y + rx2 / 4;
+        while px < py {
+            plot(self, x, y);
+            x += 1;
+            px += 2 * ry2;
+            if p < 0 {
+                p += ry2 + px;
+            } else {
+                y -= 1;
+                py -= 2 * rx2;
+                p += ry2 + px - py;
+            }
+        }
+
+        //and below, step y
+        p = ry2 * (x * x + x) + ry2 / 4 + rx2 * (y - 1) * (y - 1) - rx2 * ry2;
+        while y >= 0 {
+            plot(self, x, y);
+            y -= 1;
+            py -= 2 * rx2;
+            if p > 0 {
+                p += rx2 - py;
+            } else {
+                x += 1;
+                px += 2 * ry2;
+                p += rx2 - py + px;
+            }
+        }
+    }
+
+    //Fill the polygon through `points` (closed, even-odd rule). A pixel is
+    //inside if its top left corner is.
+    pub fn fill_polygon(&mut self, points: &[(i32, i32)], color: [u8; 4]) {
+        let (top, bottom) = match (
+            points.iter().map(|point| point.1).min(),
+            points.iter().map(|point| point.1).max(),
+        ) {
+            (Some(top), Some(bottom)) => (top, bottom),
+            _ => return,
+        };
+        let top = top.max(self.clip.y as i32);
+        let bottom = bottom.min(self.clip.bottom() as i32);
+        let bytes = self.encode(color);
+        let mut crossings = Vec::with_capacity(points.len());
+        for y in top..bottom {
+            crossings.clear();
+            let edges = points.iter().zip(points.iter().cycle().skip(1));
+            for (&(x0, y0), &(x1, y1)) in edges {
+                if (y0 <= y) != (y1 <= y) {
+                    let x = x0 as i128
+                        + (y as i128 - y0 as i128) * (x1 as i128 - x0 as i128)
+                            / (y1 as i128 - y0 as i128);
+                    //between x0 and x1, so it fits
+                    crossings.push(x as i32);
+                }
+            }
+            crossings.sort_unstable();
+            for pair in crossings.chunks_exact(2) {
+                self.span(y, pair[0], pair[1], bytes);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{lit, test_buffer, test_info, WHITE};
+    use super::*;
+
+    #[test_case]
+    fn lines_include_both_ends() {
+        let mut buffer = test_buffer();
+        let mut canvas = Canvas::new(&mut buffer, test_info());
+        canvas.line(3, 1, 0, 4, WHITE);
+        drop(canvas);
+        assert_eq!(lit(&buffer), [(3, 1), (2, 2), (1, 3), (0, 4)]);
+    }
+
+    #[test_case]
+    fn lines_are_clipped() {
+        let mut buffer = test_buffer();
+        let mut canvas = Canvas::new(&mut buffer, test_info());
+        canvas.line(-100, -100, 100, 100, WHITE);
+        canvas.line(i32::MIN, 3, i32::MAX, 3, WHITE);
+        canvas.line(i32::MAX, i32::MIN, i32::MAX, i32::MAX, WHITE); // off-screen
+        canvas.line(-5, 20, 30, 40, WHITE); // crosses no edge of the canvas
+        drop(canvas);
+        let points = lit(&buffer);
+        assert_eq!(points.len(), 16 + 15);
+        assert!((0..16).all(|i| points.contains(&(i, i)) && points.contains(&(i, 3))));
+
+        let mut buffer = test_buffer();
+        let mut canvas = Canvas::new(&mut buffer, test_info());
+        canvas.line(i32::MIN, i32::MIN, i32::MAX, i32::MAX, WHITE);
+        drop(canvas);
+        assert_eq!(lit(&buffer), (0..16).map(|i| (i, i)).collect::<Vec<_>>());
+    }
+
+    #[test_case]
+    fn rect_outline() {
+        let mut buffer = test_buffer();
+        Canvas::new(&mut buffer, test_info()).rect(1, 1, 3, 3, WHITE);
+        assert_eq!(lit(&buffer).len(), 8);
+        assert!(!lit(&buffer).contains(&(2, 2)));
+
+        //right and bottom edges beyond i32::MAX
+        let mut buffer = test_buffer();
+        Canvas::new(&mut buffer, test_info()).rect(10, 12, i32::MAX, i32::MAX, WHITE);
+        assert_eq!(lit(&buffer).len(), 6 + 3);
+    }
+
+    #[test_case]
+    fn circles_are_symmetric() {
+        let mut buffer = test_buffer();
+        Canvas::new(&mut buffer, test_info()).circle(8, 8, 5, WHITE);
+        let points = lit(&buffer);
+        for &(x, y) in &points {
+            assert!(points.contains(&(16 - x, y)));
+            assert!(points.contains(&(y, x)));
+        }
+        assert!(points.contains(&(13, 8)) && points.contains(&(8, 3)));
+        assert!(!points.contains(&(8, 8)));
+    }
+
+    #[test_case]
+    fn ellipse_reaches_its_radii() {
+        let mut buffer = test_buffer();
+        Canvas::new(&mut buffer, test_info()).ellipse(8, 8, 6, 2, WHITE);
+        let points = lit(&buffer);
+        for point in [(2, 8), (14, 8), (8, 6), (8, 10)] {
+            assert!(points.contains(&point));
+        }
+        assert!(points.iter().all(|&(_, y)| (6..=10).contains(&y)));
+    }
+
+    #[test_case]
+    fn large_ellipses_do_not_overflow() {
+        let mut buffer = test_buffer();
+        let mut canvas = Canvas::new(&mut buffer, test_info());
+        canvas.circle(8, 8, 60_000, WHITE);
+        //off the canvas, returns before the first step
+        canvas.ellipse(i32::MIN, 8, i32::MAX - 100, i32::MAX, WHITE);
+        drop(canvas);
+        assert!(lit(&buffer).is_empty());
+
+        //only the part of the outline on the canvas is drawn
+        let mut buffer = test_buffer();
+        Canvas::new(&mut buffer, test_info()).circle(-59_995, 8, 60_000, WHITE);
+        let points = lit(&buffer);
+        assert!(points.contains(&(5, 8)));
+        assert!(points.iter().all(|&(x, _)| x <= 5));
+    }
+
+    #[test_case]
+    fn polygons_are_filled() {
+        let mut buffer = test_buffer();
+        let square = [(2, 2), (6, 2), (6, 6), (2, 6)];
+        Canvas::new(&mut buffer, test_info()).fill_polygon(&square, WHITE);
+        assert_eq!(lit(&buffer).len(), 16);
+
+        let mut buffer = test_buffer();
+        let triangle = [(0, 0), (8, 0), (0, 8)];
+        Canvas::new(&mut buffer, test_info()).fill_polygon(&triangle, WHITE);
+        let points = lit(&buffer);
+        assert!(points.contains(&(0, 7)) && points.contains(&(7, 0)));
+        assert!(!points.contains(&(7, 7)));
+
+        let mut buffer = test_buffer();
+        let huge = [(i32::MIN, 0), (i32::MAX, 0), (i32::MAX, 4), (i32::MIN, 4)];
+        Canvas::new(&mut buffer, test_info()).fill_polygon(&huge, WHITE);
+        assert_eq!(lit(&buffer).len(), 4 * 16);
+    }
+}
@@ -1,40 +1,17 @@
-use crate::print;
-use crate::println;
-use crate::FRAME_BUFFER_WRITER;
-use core::fmt::Write;
-use pc_keyboard::KeyCode;
+use crate::acpi;
+use crate::gdt;
+use crate::keyboard;
+use crate::thread;
+use crate::time;
+use core::sync::atomic::{AtomicBool, Ordering};
 use pic8259::ChainedPics;
 use spin;
+use x86_64::structures::idt::HandlerFunc;
 use x86_64::structures::idt::InterruptDescriptorTable;
 use x86_64::structures::idt::InterruptStackFrame;
 
-extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-    println!("EXCEPTION: BREAKPOINT\n Stack Frame:\n {:#?}", stack_frame);
-}
-
-extern "x86-interrupt" fn double_fault_handler(
-    stack_frame: InterruptStackFrame,
-    _error_code: u64,
-) -> ! {
-    panic!("EXCEPTION: DOUBLE FAULT\n Stack Frame:\n{:#?}", stack_frame);
-}
-
-extern "x86-interrupt" fn general_protection_handler(
-    stack_frame: InterruptStackFrame,
-    _error_code: u64,
-) {
-    println!(
-        "EXCEPTION: GENERAL PROTECTION\n Error Code: {:#?}\n Stack Frame:\n{:#?}",
-        _error_code, stack_frame
-    );
-}
-
-extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
-    println!(
-        "EXCEPTION: INVALID OPCODE\n Stack Frame:\n {:#?}",
-        stack_frame
-    );
-}
+pub mod apic;
+mod exceptions;
 
 const PIC_1_OFFSET: u8 = 32;
 const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
@@ -51,6 +28,11 @@ fn init_pics() {
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET, //offset 0 is reserved for timer
     Keyboard,
+    //IRQ 7 and 15, where the 8259 delivers its spurious interrupts; they
+    //still arrive after the PIC was masked in favour of the APIC
+    PicSpurious = PIC_1_OFFSET + 7,
+    PicSpuriousSecondary = PIC_2_OFFSET + 7,
+    Spurious = 0xff, //APIC spurious interrupts, never acknowledged
 }
 
 impl InterruptIndex {
@@ -62,91 +44,165 @@ impl InterruptIndex {
         usize::from(self.as_u8())
     }
 }
-//Add a handler for Timer
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    // print!("."); //You can uncomment this to see that timer interrupt is on.
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
+
+//Legacy ISA IRQ of the keyboard, the timer comes from the Local APIC instead.
+const KEYBOARD_IRQ: u8 = 1;
+
+static APIC_ENABLED: AtomicBool = AtomicBool::new(false);
+//Set when the I/O APIC could not take IRQ 1 over and the PIC still delivers
+//the keyboard interrupts.
+static KEYBOARD_ON_PIC: AtomicBool = AtomicBool::new(false);
+
+pub fn apic_enabled() -> bool {
+    APIC_ENABLED.load(Ordering::Relaxed)
+}
+
+//Acknowledge a hardware interrupt at whichever controller delivered it.
+fn end_of_interrupt(vector: u8) {
+    let from_pic = !apic_enabled()
+        || (vector == InterruptIndex::Keyboard.as_u8() && KEYBOARD_ON_PIC.load(Ordering::Relaxed));
+    match apic::LocalApic::get() {
+        Some(local_apic) if !from_pic => local_apic.end_of_interrupt(),
+        _ => unsafe { PICS.lock().notify_end_of_interrupt(vector) },
     }
 }
 
-//Add a handler for keyboard
-extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
-    use spin::Mutex;
-    use x86_64::instructions::port::Port;
+//Vectors that drivers can request at runtime. Every one of them has an IDT
+//stub that forwards to the registered handler and sends the EOI.
+const DYNAMIC_VECTOR_BASE: u8 = PIC_2_OFFSET + 8;
+const DYNAMIC_VECTOR_COUNT: usize = 16;
 
-    lazy_static! {
-        static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> = Mutex::new(
-            Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore)
-        );
+static DYNAMIC_HANDLERS: spin::Mutex<[Option<fn()>; DYNAMIC_VECTOR_COUNT]> =
+    spin::Mutex::new([None; DYNAMIC_VECTOR_COUNT]);
+
+fn dispatch(vector: u8) {
+    let handler = DYNAMIC_HANDLERS.lock()[(vector - DYNAMIC_VECTOR_BASE) as usize];
+    if let Some(handler) = handler {
+        handler();
     }
+    end_of_interrupt(vector);
+}
 
-    let mut keyboard = KEYBOARD.lock();
-    let mut port = Port::new(0x60);
+macro_rules! dynamic_stubs {
+    ($($index:literal),*) => {
+        [$({
+            extern "x86-interrupt" fn stub(_stack_frame: InterruptStackFrame) {
+                dispatch(DYNAMIC_VECTOR_BASE + $index);
+            }
+            stub as HandlerFunc
+        }),*]
+    };
+}
 
-    let scancode: u8 = unsafe { port.read() };
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-        if let Some(key) = keyboard.process_keyevent(key_event) {
-            match key {
-                //DecodedKey::Unicode(character) => print!("{}", character),
-                DecodedKey::Unicode(character) => {
-                    if character == '\u{0008}' {
-                        // Check for backspace character
-                        unsafe {
-                            FRAME_BUFFER_WRITER.unwrap().as_mut().backspace();
-                        }
-                    } else if character == '\u{0009}' {
-                        unsafe {
-                            FRAME_BUFFER_WRITER.unwrap().as_mut().tab();
-                        }
-                    } else {
-                        print!("{}", character);
-                    }
-                }
-                DecodedKey::RawKey(key) => {
-                    if key == KeyCode::ArrowUp {
-                        unsafe {
-                            FRAME_BUFFER_WRITER.unwrap().as_mut().arrow_up();
-                        }
-                    } else if key == KeyCode::ArrowDown {
-                        unsafe {
-                            FRAME_BUFFER_WRITER.unwrap().as_mut().arrow_down();
-                        }
-                    } else if key == KeyCode::ArrowRight {
-                        unsafe {
-                            FRAME_BUFFER_WRITER.unwrap().as_mut().arrow_right();
-                        }
-                    } else if key == KeyCode::ArrowLeft {
-                        unsafe {
-                            FRAME_BUFFER_WRITER.unwrap().as_mut().arrow_left();
-                        }
-                    }
-                }
+static DYNAMIC_STUBS: [HandlerFunc; DYNAMIC_VECTOR_COUNT] =
+    dynamic_stubs!(0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15);
+
+//Reserve a free vector and run `handler` (in interrupt context) whenever it
+//fires. Returns None when all dynamic vectors are taken.
+#[allow(dead_code)]
+pub fn allocate_vector(handler: fn()) -> Option<u8> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut handlers = DYNAMIC_HANDLERS.lock();
+        let index = handlers.iter().position(Option::is_none)?;
+        handlers[index] = Some(handler);
+        Some(DYNAMIC_VECTOR_BASE + index as u8)
+    })
+}
+
+#[allow(dead_code)]
+pub fn free_vector(vector: u8) {
+    if let Some(index) = vector.checked_sub(DYNAMIC_VECTOR_BASE) {
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            if let Some(handler) = DYNAMIC_HANDLERS.lock().get_mut(index as usize) {
+                *handler = None;
             }
-        }
+        });
     }
+}
+
+//Deliver ISA interrupt `irq` on `vector`, following the ACPI interrupt source
+//overrides. Only works with the I/O APIC, the 8259 PIC can't move IRQs to
+//other vectors.
+pub fn route_irq(irq: u8, vector: u8) -> bool {
+    let (io_apic, local_apic) = match (apic::IoApic::get(), apic::LocalApic::get()) {
+        (Some(io_apic), Some(local_apic)) if apic_enabled() => (io_apic, local_apic),
+        _ => return false,
+    };
+
+    let madt = acpi::info().and_then(|info| info.madt.as_ref());
+    let gsi_base = madt
+        .and_then(|madt| madt.io_apics.first())
+        .map_or(0, |io_apic| io_apic.gsi_base);
+    let (gsi, active_low, level_triggered) =
+        match madt.and_then(|madt| madt.interrupt_override(irq)) {
+            Some(entry) => (entry.gsi, entry.active_low(), entry.level_triggered()),
+            None => (irq as u32, false, false),
+        };
 
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
+    match gsi.checked_sub(gsi_base) {
+        Some(input) if input <= io_apic.max_redirection_entry() as u32 => {
+            io_apic.route(
+                input as u8,
+                vector,
+                local_apic.id(),
+                active_low,
+                level_triggered,
+            );
+            true
+        }
+        _ => false, // belongs to another I/O APIC
     }
 }
 
+//Add a handler for Timer
+extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    // print!("."); //You can uncomment this to see that timer interrupt is on.
+    time::tick();
+    end_of_interrupt(InterruptIndex::Timer.as_u8());
+    thread::preempt(); //round robin, may switch to another kernel thread
+}
+
+//Add a handler for keyboard. It only queues the scancode, decoding and drawing
+//happen outside interrupt context, in the task reading keyboard::ScancodeStream.
+extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    use x86_64::instructions::port::Port;
+
+    let mut port = Port::new(0x60);
+    let scancode: u8 = unsafe { port.read() };
+    keyboard::add_scancode(scancode);
+
+    end_of_interrupt(InterruptIndex::Keyboard.as_u8());
+}
+
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {}
+
+//Nothing uses IRQ 7, so anything on its vector is spurious and gets no EOI.
+extern "x86-interrupt" fn pic_spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {}
+
+//Same for IRQ 15, except that the first PIC did see a real interrupt on its
+//cascade line and still needs an EOI.
+extern "x86-interrupt" fn pic_spurious_secondary_interrupt_handler(
+    _stack_frame: InterruptStackFrame,
+) {
+    unsafe { PICS.lock().notify_end_of_interrupt(PIC_1_OFFSET + 2) };
+}
+
 //setup the IDT and make entries of all the handlers
 use lazy_static::lazy_static;
 
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
-        idt.double_fault.set_handler_fn(double_fault_handler);
-        idt.general_protection_fault
-            .set_handler_fn(general_protection_handler);
-        idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+        exceptions::register(&mut idt);
         idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
         idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::PicSpurious.as_usize()].set_handler_fn(pic_spurious_interrupt_handler);
+        idt[InterruptIndex::PicSpuriousSecondary.as_usize()]
+            .set_handler_fn(pic_spurious_secondary_interrupt_handler);
+        idt[InterruptIndex::Spurious.as_usize()].set_handler_fn(spurious_interrupt_handler);
+        for (index, stub) in DYNAMIC_STUBS.iter().enumerate() {
+            idt[DYNAMIC_VECTOR_BASE as usize + index].set_handler_fn(*stub);
+        }
         idt
     };
 }
@@ -157,9 +213,75 @@ fn init_idt() {
     IDT.load();
 }
 
+//Move the timer and keyboard over to the Local and I/O APIC and switch the
+//8259 PIC off. If the APIC can't be used the PIC stays in charge, if only the
+//keyboard can't be routed through the I/O APIC the PIC keeps delivering it.
+fn init_apic() {
+    let frequency = time::frequency_hz() as u32;
+    let io_apic_address = acpi::info()
+        .and_then(|info| info.madt.as_ref())
+        .and_then(|madt| madt.io_apics.first())
+        .map_or(apic::DEFAULT_IO_APIC_ADDRESS, |io_apic| {
+            io_apic.address as u64
+        });
+    match apic::init(
+        io_apic_address,
+        InterruptIndex::Spurious.as_u8(),
+        InterruptIndex::Timer.as_u8(),
+        frequency,
+    ) {
+        Ok(timer_frequency) => {
+            APIC_ENABLED.store(true, Ordering::Relaxed);
+            time::set_tick_frequency(timer_frequency);
+            if route_irq(KEYBOARD_IRQ, InterruptIndex::Keyboard.as_u8()) {
+                unsafe { PICS.lock().disable() };
+            } else {
+                log::warn!("I/O APIC can't deliver the keyboard IRQ, leaving it on the 8259 PIC");
+                KEYBOARD_ON_PIC.store(true, Ordering::Relaxed);
+                //the timer comes from the Local APIC now, only IRQ 1 stays unmasked
+                unsafe { PICS.lock().write_masks(!(1 << KEYBOARD_IRQ), u8::MAX) };
+            }
+        }
+        Err(err) => {
+            log::warn!("APIC not available ({:?}), using the 8259 PIC", err);
+        }
+    }
+}
+
 //init all interrupts
 pub fn init() {
+    gdt::init(); //GDT and TSS, the IDT below refers to its IST stacks
     init_idt(); //IDT
-    init_pics(); //PICS
+    init_pics(); //PICS, remapped even if we switch to the APIC below
+    init_apic(); //APIC, with the PICS as fallback
     x86_64::instructions::interrupts::enable(); //enable hardware interrupts. Without handler for timer interrupt, which is on by default, there will be a double fault
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn breakpoint_returns() {
+        x86_64::instructions::interrupts::int3();
+    }
+
+    #[test_case]
+    fn timer_interrupt_ticks() {
+        let start = time::ticks();
+        while time::ticks() == start {
+            x86_64::instructions::hlt();
+        }
+    }
+
+    fn ignore() {}
+
+    #[test_case]
+    fn dynamic_vectors_are_reused() {
+        let vector = allocate_vector(ignore).unwrap();
+        assert!(vector >= DYNAMIC_VECTOR_BASE);
+        free_vector(vector);
+        assert_eq!(allocate_vector(ignore), Some(vector));
+        free_vector(vector);
+    }
+}
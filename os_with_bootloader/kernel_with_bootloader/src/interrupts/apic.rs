@@ -0,0 +1,209 @@
+use crate::memory::VMM;
+use crate::time;
+use core::arch::x86_64::__cpuid;
+use core::ptr;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::registers::model_specific::Msr;
+use x86_64::PhysAddr;
+
+const IA32_APIC_BASE_MSR: u32 = 0x1b;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+
+//Local APIC registers, offsets from its MMIO base.
+const LAPIC_ID: usize = 0x20;
+const LAPIC_TPR: usize = 0x80;
+const LAPIC_EOI: usize = 0xb0;
+const LAPIC_SVR: usize = 0xf0;
+const LAPIC_LVT_TIMER: usize = 0x320;
+const LAPIC_LVT_LINT0: usize = 0x350;
+const LAPIC_LVT_LINT1: usize = 0x360;
+const LAPIC_LVT_ERROR: usize = 0x370;
+const LAPIC_TIMER_INITIAL_COUNT: usize = 0x380;
+const LAPIC_TIMER_CURRENT_COUNT: usize = 0x390;
+const LAPIC_TIMER_DIVIDE: usize = 0x3e0;
+
+const SVR_APIC_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+const LVT_DELIVERY_NMI: u32 = 0b100 << 8;
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+
+//I/O APIC registers are reached through a select/window register pair.
+pub const DEFAULT_IO_APIC_ADDRESS: u64 = 0xfec0_0000;
+const IO_APIC_REGSEL: usize = 0x00;
+const IO_APIC_WINDOW: usize = 0x10;
+const IO_APIC_VERSION: u32 = 0x01;
+const IO_APIC_REDIRECTION_TABLE: u32 = 0x10;
+const REDIRECTION_ACTIVE_LOW: u32 = 1 << 13;
+const REDIRECTION_LEVEL_TRIGGERED: u32 = 1 << 15;
+
+const CALIBRATION_MS: u32 = 10;
+
+//Virtual addresses of the mapped registers, 0 while not mapped.
+static LOCAL_APIC: AtomicU64 = AtomicU64::new(0);
+static IO_APIC: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug)]
+pub enum ApicError {
+    NotSupported,
+    MappingFailed,
+}
+
+pub fn is_supported() -> bool {
+    let cpuid = unsafe { __cpuid(1) };
+    cpuid.edx & (1 << 9) != 0
+}
+
+fn map_registers(phys: u64) -> Result<u64, ApicError> {
+    let mut vmm = VMM.lock();
+    let vmm = vmm.as_mut().ok_or(ApicError::MappingFailed)?;
+    vmm.map_mmio(PhysAddr::new(phys), 4096)
+        .map(|virt| virt.as_u64())
+        .map_err(|_| ApicError::MappingFailed)
+}
+
+pub struct LocalApic {
+    base: u64,
+}
+
+impl LocalApic {
+    //The Local APIC of the CPU we are running on, if it was enabled.
+    pub fn get() -> Option<LocalApic> {
+        match LOCAL_APIC.load(Ordering::Acquire) {
+            0 => None,
+            base => Some(LocalApic { base }),
+        }
+    }
+
+    fn read(&self, register: usize) -> u32 {
+        unsafe { ptr::read_volatile((self.base as usize + register) as *const u32) }
+    }
+
+    fn write(&self, register: usize, value: u32) {
+        unsafe { ptr::write_volatile((self.base as usize + register) as *mut u32, value) }
+    }
+
+    pub fn id(&self) -> u8 {
+        (self.read(LAPIC_ID) >> 24) as u8
+    }
+
+    pub fn end_of_interrupt(&self) {
+        self.write(LAPIC_EOI, 0);
+    }
+
+    fn enable(&self, spurious_vector: u8) {
+        self.write(LAPIC_TPR, 0); // accept all priorities
+        self.write(LAPIC_LVT_LINT0, LVT_MASKED);
+        self.write(LAPIC_LVT_LINT1, LVT_DELIVERY_NMI);
+        self.write(LAPIC_LVT_ERROR, LVT_MASKED);
+        self.write(LAPIC_SVR, SVR_APIC_ENABLE | spurious_vector as u32);
+    }
+
+    //Count how fast the APIC timer runs against the PIT, then start it in
+    //periodic mode at `frequency_hz`.
+    fn start_timer(&self, vector: u8, frequency_hz: u32) -> u32 {
+        self.write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+        self.write(LAPIC_LVT_TIMER, LVT_MASKED);
+        self.write(LAPIC_TIMER_INITIAL_COUNT, u32::MAX);
+        time::pit::busy_wait_ms(CALIBRATION_MS);
+        let elapsed = u32::MAX - self.read(LAPIC_TIMER_CURRENT_COUNT);
+
+        let counts_per_second = elapsed as u64 * (1000 / CALIBRATION_MS as u64);
+        let initial_count = (counts_per_second / frequency_hz.max(1) as u64).max(1);
+        self.write(LAPIC_LVT_TIMER, LVT_TIMER_PERIODIC | vector as u32);
+        self.write(LAPIC_TIMER_INITIAL_COUNT, initial_count as u32);
+
+        (counts_per_second / initial_count) as u32
+    }
+}
+
+pub struct IoApic {
+    base: u64,
+}
+
+impl IoApic {
+    pub fn get() -> Option<IoApic> {
+        match IO_APIC.load(Ordering::Acquire) {
+            0 => None,
+            base => Some(IoApic { base }),
+        }
+    }
+
+    fn read(&self, register: u32) -> u32 {
+        unsafe {
+            ptr::write_volatile((self.base as usize + IO_APIC_REGSEL) as *mut u32, register);
+            ptr::read_volatile((self.base as usize + IO_APIC_WINDOW) as *const u32)
+        }
+    }
+
+    fn write(&self, register: u32, value: u32) {
+        unsafe {
+            ptr::write_volatile((self.base as usize + IO_APIC_REGSEL) as *mut u32, register);
+            ptr::write_volatile((self.base as usize + IO_APIC_WINDOW) as *mut u32, value);
+        }
+    }
+
+    pub fn max_redirection_entry(&self) -> u8 {
+        ((self.read(IO_APIC_VERSION) >> 16) & 0xff) as u8
+    }
+
+    //Deliver input `gsi` as `vector` to the Local APIC `destination`. ISA
+    //interrupts are edge triggered and active high unless ACPI says otherwise.
+    pub fn route(
+        &self,
+        gsi: u8,
+        vector: u8,
+        destination: u8,
+        active_low: bool,
+        level_triggered: bool,
+    ) {
+        let register = IO_APIC_REDIRECTION_TABLE + 2 * gsi as u32;
+        let mut low = vector as u32;
+        if active_low {
+            low |= REDIRECTION_ACTIVE_LOW;
+        }
+        if level_triggered {
+            low |= REDIRECTION_LEVEL_TRIGGERED;
+        }
+        self.write(register + 1, (destination as u32) << 24);
+        self.write(register, low);
+    }
+
+    pub fn mask(&self, gsi: u8) {
+        let register = IO_APIC_REDIRECTION_TABLE + 2 * gsi as u32;
+        self.write(register, LVT_MASKED);
+    }
+
+    fn mask_all(&self) {
+        for gsi in 0..=self.max_redirection_entry() {
+            self.mask(gsi);
+        }
+    }
+}
+
+//Map and enable the Local APIC and the I/O APIC. Returns the frequency the
+//APIC timer runs at. The caller is responsible for disabling the 8259 PIC.
+pub fn init(
+    io_apic_address: u64,
+    spurious_vector: u8,
+    timer_vector: u8,
+    timer_frequency_hz: u32,
+) -> Result<u32, ApicError> {
+    if !is_supported() {
+        return Err(ApicError::NotSupported);
+    }
+
+    let mut base_msr = Msr::new(IA32_APIC_BASE_MSR);
+    let base = unsafe { base_msr.read() };
+    unsafe { base_msr.write(base | APIC_BASE_ENABLE) };
+
+    let local_apic = map_registers(base & 0xf_ffff_f000)?;
+    let io_apic = map_registers(io_apic_address)?;
+    LOCAL_APIC.store(local_apic, Ordering::Release);
+    IO_APIC.store(io_apic, Ordering::Release);
+
+    IoApic::get().unwrap().mask_all();
+    let local_apic = LocalApic::get().unwrap();
+    local_apic.enable(spurious_vector);
+    Ok(local_apic.start_timer(timer_vector, timer_frequency_hz))
+}
@@ -0,0 +1,292 @@
+use crate::gdt;
+use core::fmt;
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::InterruptDescriptorTable;
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::structures::idt::PageFaultErrorCode;
+
+//Error code pushed by #TS, #NP, #SS and #GP when a segment selector is involved.
+pub struct SelectorErrorCode(u64);
+
+impl SelectorErrorCode {
+    pub fn external(&self) -> bool {
+        self.0 & 0b1 != 0
+    }
+
+    pub fn table(&self) -> &'static str {
+        match (self.0 >> 1) & 0b11 {
+            0b00 => "GDT",
+            0b01 | 0b11 => "IDT",
+            _ => "LDT",
+        }
+    }
+
+    pub fn index(&self) -> u64 {
+        (self.0 >> 3) & 0x1fff
+    }
+}
+
+impl fmt::Display for SelectorErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 == 0 {
+            return write!(f, "{:#x} (not selector related)", self.0);
+        }
+        write!(
+            f,
+            "{:#x} (table: {}, index: {}, external: {})",
+            self.0,
+            self.table(),
+            self.index(),
+            self.external()
+        )
+    }
+}
+
+//Faults leave RIP on the faulting instruction, so returning from one of these
+//would just run into the same exception again. They all end in a panic.
+extern "x86-interrupt" fn divide_error_handler(stack_frame: InterruptStackFrame) {
+    panic!("EXCEPTION: DIVIDE ERROR\n Stack Frame:\n{:#?}", stack_frame);
+}
+
+extern "x86-interrupt" fn debug_handler(stack_frame: InterruptStackFrame) {
+    log::debug!("EXCEPTION: DEBUG\n Stack Frame:\n{:#?}", stack_frame);
+}
+
+extern "x86-interrupt" fn nmi_handler(stack_frame: InterruptStackFrame) {
+    log::error!(
+        "EXCEPTION: NON-MASKABLE INTERRUPT\n Stack Frame:\n{:#?}",
+        stack_frame
+    );
+}
+
+extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+    log::info!("EXCEPTION: BREAKPOINT\n Stack Frame:\n{:#?}", stack_frame);
+}
+
+extern "x86-interrupt" fn overflow_handler(stack_frame: InterruptStackFrame) {
+    log::warn!("EXCEPTION: OVERFLOW\n Stack Frame:\n{:#?}", stack_frame);
+}
+
+extern "x86-interrupt" fn bound_range_exceeded_handler(stack_frame: InterruptStackFrame) {
+    panic!(
+        "EXCEPTION: BOUND RANGE EXCEEDED\n Stack Frame:\n{:#?}",
+        stack_frame
+    );
+}
+
+extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
+    panic!(
+        "EXCEPTION: INVALID OPCODE\n Stack Frame:\n {:#?}",
+        stack_frame
+    );
+}
+
+extern "x86-interrupt" fn device_not_available_handler(stack_frame: InterruptStackFrame) {
+    panic!(
+        "EXCEPTION: DEVICE NOT AVAILABLE\n Stack Frame:\n{:#?}",
+        stack_frame
+    );
+}
+
+extern "x86-interrupt" fn double_fault_handler(
+    stack_frame: InterruptStackFrame,
+    _error_code: u64,
+) -> ! {
+    panic!("EXCEPTION: DOUBLE FAULT\n Stack Frame:\n{:#?}", stack_frame);
+}
+
+extern "x86-interrupt" fn invalid_tss_handler(stack_frame: InterruptStackFrame, error_code: u64) {
+    panic!(
+        "EXCEPTION: INVALID TSS\n Error Code: {}\n Stack Frame:\n{:#?}",
+        SelectorErrorCode(error_code),
+        stack_frame
+    );
+}
+
+extern "x86-interrupt" fn segment_not_present_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    panic!(
+        "EXCEPTION: SEGMENT NOT PRESENT\n Error Code: {}\n Stack Frame:\n{:#?}",
+        SelectorErrorCode(error_code),
+        stack_frame
+    );
+}
+
+extern "x86-interrupt" fn stack_segment_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    panic!(
+        "EXCEPTION: STACK SEGMENT FAULT\n Error Code: {}\n Stack Frame:\n{:#?}",
+        SelectorErrorCode(error_code),
+        stack_frame
+    );
+}
+
+extern "x86-interrupt" fn general_protection_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    panic!(
+        "EXCEPTION: GENERAL PROTECTION\n Error Code: {}\n Stack Frame:\n{:#?}",
+        SelectorErrorCode(error_code),
+        stack_frame
+    );
+}
+
+extern "x86-interrupt" fn page_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    let address = Cr2::read();
+
+    let cause = if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        "protection violation on a present page"
+    } else {
+        "page not present"
+    };
+    let access = if error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+        "instruction fetch"
+    } else if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+        "write"
+    } else {
+        "read"
+    };
+    let mode = if error_code.contains(PageFaultErrorCode::USER_MODE) {
+        "user"
+    } else {
+        "kernel"
+    };
+
+    panic!(
+        "EXCEPTION: PAGE FAULT\n Accessed Address: {:?}\n Cause: {}, {} access in {} mode\n Reserved Bit Set: {}\n Faulting RIP: {:?}\n Error Code: {:?}\n Stack Frame:\n{:#?}",
+        address,
+        cause,
+        access,
+        mode,
+        error_code.contains(PageFaultErrorCode::MALFORMED_TABLE),
+        stack_frame.instruction_pointer,
+        error_code,
+        stack_frame
+    );
+}
+
+extern "x86-interrupt" fn x87_floating_point_handler(stack_frame: InterruptStackFrame) {
+    panic!(
+        "EXCEPTION: x87 FLOATING POINT\n Stack Frame:\n{:#?}",
+        stack_frame
+    );
+}
+
+extern "x86-interrupt" fn alignment_check_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    panic!(
+        "EXCEPTION: ALIGNMENT CHECK\n Error Code: {:#x}\n Stack Frame:\n{:#?}",
+        error_code, stack_frame
+    );
+}
+
+extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    panic!(
+        "EXCEPTION: MACHINE CHECK\n Stack Frame:\n{:#?}",
+        stack_frame
+    );
+}
+
+extern "x86-interrupt" fn simd_floating_point_handler(stack_frame: InterruptStackFrame) {
+    panic!(
+        "EXCEPTION: SIMD FLOATING POINT\n Stack Frame:\n{:#?}",
+        stack_frame
+    );
+}
+
+extern "x86-interrupt" fn virtualization_handler(stack_frame: InterruptStackFrame) {
+    panic!(
+        "EXCEPTION: VIRTUALIZATION\n Stack Frame:\n{:#?}",
+        stack_frame
+    );
+}
+
+extern "x86-interrupt" fn cp_protection_handler(stack_frame: InterruptStackFrame, error_code: u64) {
+    panic!(
+        "EXCEPTION: CONTROL PROTECTION\n Error Code: {:#x}\n Stack Frame:\n{:#?}",
+        error_code, stack_frame
+    );
+}
+
+extern "x86-interrupt" fn hv_injection_handler(stack_frame: InterruptStackFrame) {
+    panic!(
+        "EXCEPTION: HYPERVISOR INJECTION\n Stack Frame:\n{:#?}",
+        stack_frame
+    );
+}
+
+extern "x86-interrupt" fn vmm_communication_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    panic!(
+        "EXCEPTION: VMM COMMUNICATION\n Error Code: {:#x}\n Stack Frame:\n{:#?}",
+        error_code, stack_frame
+    );
+}
+
+extern "x86-interrupt" fn security_exception_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    panic!(
+        "EXCEPTION: SECURITY\n Error Code: {:#x}\n Stack Frame:\n{:#?}",
+        error_code, stack_frame
+    );
+}
+
+//Make IDT entries for all architectural exceptions.
+pub fn register(idt: &mut InterruptDescriptorTable) {
+    idt.divide_error.set_handler_fn(divide_error_handler);
+    idt.debug.set_handler_fn(debug_handler);
+    idt.breakpoint.set_handler_fn(breakpoint_handler);
+    idt.overflow.set_handler_fn(overflow_handler);
+    idt.bound_range_exceeded
+        .set_handler_fn(bound_range_exceeded_handler);
+    idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+    idt.device_not_available
+        .set_handler_fn(device_not_available_handler);
+    idt.invalid_tss.set_handler_fn(invalid_tss_handler);
+    idt.segment_not_present
+        .set_handler_fn(segment_not_present_handler);
+    idt.stack_segment_fault
+        .set_handler_fn(stack_segment_fault_handler);
+    idt.general_protection_fault
+        .set_handler_fn(general_protection_handler);
+    idt.page_fault.set_handler_fn(page_fault_handler);
+    idt.x87_floating_point
+        .set_handler_fn(x87_floating_point_handler);
+    idt.alignment_check.set_handler_fn(alignment_check_handler);
+    idt.simd_floating_point
+        .set_handler_fn(simd_floating_point_handler);
+    idt.virtualization.set_handler_fn(virtualization_handler);
+    idt.cp_protection_exception
+        .set_handler_fn(cp_protection_handler);
+    idt.hv_injection_exception
+        .set_handler_fn(hv_injection_handler);
+    idt.vmm_communication_exception
+        .set_handler_fn(vmm_communication_handler);
+    idt.security_exception
+        .set_handler_fn(security_exception_handler);
+    unsafe {
+        idt.double_fault
+            .set_handler_fn(double_fault_handler)
+            .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        idt.non_maskable_interrupt
+            .set_handler_fn(nmi_handler)
+            .set_stack_index(gdt::NMI_IST_INDEX);
+        idt.machine_check
+            .set_handler_fn(machine_check_handler)
+            .set_stack_index(gdt::MACHINE_CHECK_IST_INDEX);
+    }
+}
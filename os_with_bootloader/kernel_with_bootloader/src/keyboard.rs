@@ -0,0 +1,120 @@
+use conquer_once::spin::OnceCell;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+use crossbeam_queue::ArrayQueue;
+use futures_util::stream::Stream;
+use futures_util::task::AtomicWaker;
+use lazy_static::lazy_static;
+use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, KeyState, Keyboard, ScancodeSet1};
+use spin::Mutex;
+
+const SCANCODE_QUEUE_SIZE: usize = 100;
+
+//Filled by the keyboard interrupt handler, drained outside interrupt context.
+static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+static DROPPED_SCANCODES: AtomicUsize = AtomicUsize::new(0);
+static REPORTED_DROPS: AtomicUsize = AtomicUsize::new(0);
+static WAKER: AtomicWaker = AtomicWaker::new();
+static SHIFT: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> = Mutex::new(
+        Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore)
+    );
+}
+
+//Needs the heap, the queue is allocated once here.
+pub fn init() {
+    SCANCODE_QUEUE
+        .try_init_once(|| ArrayQueue::new(SCANCODE_QUEUE_SIZE))
+        .expect("keyboard::init should only be called once");
+}
+
+//Called by the keyboard interrupt handler. Must not block or allocate.
+pub(crate) fn add_scancode(scancode: u8) {
+    match SCANCODE_QUEUE.try_get() {
+        Ok(queue) => {
+            if queue.push(scancode).is_err() {
+                DROPPED_SCANCODES.fetch_add(1, Ordering::Relaxed);
+            } else {
+                WAKER.wake();
+            }
+        }
+        // queue not initialized yet, the key is lost
+        Err(_) => {
+            DROPPED_SCANCODES.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+pub fn dropped_scancodes() -> usize {
+    DROPPED_SCANCODES.load(Ordering::Relaxed)
+}
+
+//Async view of the scancode queue. There is only one queue, so only one
+//stream should be polled at a time.
+pub struct ScancodeStream {
+    _private: (),
+}
+
+impl ScancodeStream {
+    pub fn new() -> Self {
+        ScancodeStream { _private: () }
+    }
+}
+
+impl Stream for ScancodeStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        let queue = SCANCODE_QUEUE
+            .try_get()
+            .expect("scancode queue not initialized");
+
+        // fast path
+        if let Some(scancode) = queue.pop() {
+            return Poll::Ready(Some(scancode));
+        }
+
+        WAKER.register(cx.waker());
+        match queue.pop() {
+            Some(scancode) => {
+                WAKER.take();
+                Poll::Ready(Some(scancode))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+//Warn about keys lost to a full queue since the last call.
+pub fn report_dropped_scancodes() {
+    let dropped = dropped_scancodes();
+    let reported = REPORTED_DROPS.swap(dropped, Ordering::Relaxed);
+    if dropped > reported {
+        log::warn!(
+            "scancode queue full, dropped {} keyboard input(s)",
+            dropped - reported
+        );
+    }
+}
+
+//Feed one scancode to the decoder. Returns the key once a press is complete.
+pub fn decode(scancode: u8) -> Option<DecodedKey> {
+    let mut keyboard = KEYBOARD.lock();
+    match keyboard.add_byte(scancode) {
+        Ok(Some(key_event)) => {
+            if let KeyCode::ShiftLeft | KeyCode::ShiftRight = key_event.code {
+                SHIFT.store(key_event.state == KeyState::Down, Ordering::Relaxed);
+            }
+            keyboard.process_keyevent(key_event)
+        }
+        _ => None,
+    }
+}
+
+//Whether a Shift key is held, as of the last decoded scancode.
+pub fn shift_pressed() -> bool {
+    SHIFT.load(Ordering::Relaxed)
+}
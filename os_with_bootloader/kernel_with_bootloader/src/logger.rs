@@ -0,0 +1,317 @@
+//Kernel backend for the `log` crate. Every record goes to the framebuffer
+//(level in color, errors and warnings entirely), to COM1 (plain) and into an
+//in-memory ring buffer that can be dumped later, like dmesg.
+//
+//Filters are fixed at build time through the KERNEL_LOG environment variable,
+//e.g. `KERNEL_LOG=info,memory=trace,interrupts::apic=debug cargo build`. A
+//bare level sets the default, `module=level` applies to a module and
+//everything below it; the most specific match wins.
+use crate::time;
+use crate::writer::FrameBufferWriter;
+use crate::FRAME_BUFFER_WRITER;
+use core::fmt;
+use core::fmt::Write;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use spin::Mutex;
+
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+const MAX_DIRECTIVES: usize = 16;
+const LOG_BUFFER_SIZE: usize = 16 * 1024;
+
+//Framebuffer colors, [r, g, b, a]
+const TEXT_COLOR: [u8; 4] = FrameBufferWriter::DEFAULT_COLOR;
+const DIM_COLOR: [u8; 4] = [128, 128, 128, 255];
+
+fn level_color(level: Level) -> [u8; 4] {
+    match level {
+        Level::Error => [255, 64, 64, 255],
+        Level::Warn => [255, 200, 0, 255],
+        Level::Info => [64, 255, 64, 255],
+        Level::Debug => [64, 160, 255, 255],
+        Level::Trace => [200, 128, 255, 255],
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Directive {
+    module: &'static str,
+    level: LevelFilter,
+}
+
+struct KernelLogger {
+    default_level: LevelFilter,
+    directives: [Option<Directive>; MAX_DIRECTIVES],
+}
+
+static mut LOGGER: KernelLogger = KernelLogger {
+    default_level: DEFAULT_LEVEL,
+    directives: [None; MAX_DIRECTIVES],
+};
+
+static LOG_BUFFER: Mutex<LogBuffer> = Mutex::new(LogBuffer::new());
+
+//Install the logger. Call once, first thing in the entry point: it needs neither
+//the heap nor the framebuffer writer, records logged before the writer exists
+//only go to serial and the log buffer.
+pub fn init() {
+    let logger = unsafe { &mut *core::ptr::addr_of_mut!(LOGGER) };
+    let mut invalid = None;
+    if let Some(spec) = option_env!("KERNEL_LOG") {
+        invalid = logger.parse(spec).err();
+    }
+
+    log::set_max_level(logger.max_level());
+    log::set_logger(logger).expect("logger::init should only be called once");
+
+    if let Some(directive) = invalid {
+        log::warn!("ignoring invalid KERNEL_LOG directive `{}`", directive);
+    }
+}
+
+impl KernelLogger {
+    //Returns the first directive that couldn't be parsed; the rest still apply.
+    fn parse(&mut self, spec: &'static str) -> Result<(), &'static str> {
+        let mut invalid = None;
+        let mut count = 0;
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let parsed = match directive.split_once('=') {
+                Some((module, level)) => level.trim().parse().ok().map(|level| Directive {
+                    module: module.trim(),
+                    level,
+                }),
+                None => match directive.parse() {
+                    Ok(level) => {
+                        self.default_level = level;
+                        continue;
+                    }
+                    Err(_) => None,
+                },
+            };
+            match parsed {
+                Some(parsed) if count < MAX_DIRECTIVES => {
+                    self.directives[count] = Some(parsed);
+                    count += 1;
+                }
+                _ => invalid = invalid.or(Some(directive)),
+            }
+        }
+        match invalid {
+            Some(directive) => Err(directive),
+            None => Ok(()),
+        }
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.directives
+            .iter()
+            .flatten()
+            .map(|directive| directive.level)
+            .fold(self.default_level, Ord::max)
+    }
+
+    //Targets are module paths, `kernel_with_bootloader::memory::paging`;
+    //directives leave out the crate name.
+    fn level_for(&self, target: &str) -> LevelFilter {
+        let module = target
+            .strip_prefix(env!("CARGO_CRATE_NAME"))
+            .map_or(target, |rest| rest.trim_start_matches("::"));
+        self.directives
+            .iter()
+            .flatten()
+            .filter(|directive| matches_module(module, directive.module))
+            .max_by_key(|directive| directive.module.len())
+            .map_or(self.default_level, |directive| directive.level)
+    }
+}
+
+fn matches_module(module: &str, prefix: &str) -> bool {
+    match module.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        None => false,
+    }
+}
+
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let uptime = time::uptime();
+        let timestamp = Timestamp(uptime.as_secs(), uptime.subsec_micros());
+        let module = record
+            .module_path()
+            .and_then(|path| path.strip_prefix(env!("CARGO_CRATE_NAME")))
+            .map(|rest| rest.trim_start_matches("::"))
+            .filter(|rest| !rest.is_empty())
+            .unwrap_or("kernel");
+
+        //Interrupt handlers log too, they must not find the locks taken.
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let _ = writeln!(
+                LOG_BUFFER.lock(),
+                "{} {:5} {}: {}",
+                timestamp,
+                record.level(),
+                module,
+                record.args()
+            );
+
+            crate::serial::_print(format_args!(
+                "{} {:5} {}: {}\n",
+                timestamp,
+                record.level(),
+                module,
+                record.args()
+            ));
+
+            if let Some(writer) = unsafe { FRAME_BUFFER_WRITER.as_mut() } {
+                let writer = unsafe { writer.as_mut() };
+                let color = writer.color();
+                writer.set_color(DIM_COLOR);
+                let _ = write!(writer, "{} ", timestamp);
+                writer.set_color(level_color(record.level()));
+                let _ = write!(writer, "{:5} ", record.level());
+                //errors and warnings stand out as a whole
+                if record.level() > Level::Warn {
+                    writer.set_color(TEXT_COLOR);
+                }
+                let _ = writeln!(writer, "{}: {}", module, record.args());
+                writer.set_color(color);
+            }
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+//[seconds.microseconds] since boot
+struct Timestamp(u64, u32);
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{:5}.{:06}]", self.0, self.1)
+    }
+}
+
+//Fixed size, so logging works before the heap is up and never allocates.
+//Once full the oldest messages are overwritten.
+struct LogBuffer {
+    data: [u8; LOG_BUFFER_SIZE],
+    start: usize,
+    len: usize,
+    wrapped: bool,
+}
+
+impl LogBuffer {
+    const fn new() -> Self {
+        LogBuffer {
+            data: [0; LOG_BUFFER_SIZE],
+            start: 0,
+            len: 0,
+            wrapped: false,
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        let end = (self.start + self.len) % LOG_BUFFER_SIZE;
+        self.data[end] = byte;
+        if self.len < LOG_BUFFER_SIZE {
+            self.len += 1;
+        } else {
+            self.start = (self.start + 1) % LOG_BUFFER_SIZE;
+            self.wrapped = true;
+        }
+    }
+
+    //Oldest message first. After wrapping the first line is cut off (and may
+    //start inside a UTF-8 sequence), so it's skipped.
+    fn contents(&mut self) -> &str {
+        self.data.rotate_left(self.start);
+        self.start = 0;
+        let mut bytes = &self.data[..self.len];
+        if self.wrapped {
+            let first_line = bytes.iter().position(|&byte| byte == b'\n');
+            bytes = &bytes[first_line.map_or(bytes.len(), |end| end + 1)..];
+        }
+        core::str::from_utf8(bytes).unwrap_or("")
+    }
+}
+
+impl fmt::Write for LogBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.bytes().for_each(|byte| self.push(byte));
+        Ok(())
+    }
+}
+
+//Write the recent log messages to `out`, oldest first.
+pub fn dmesg(out: &mut impl fmt::Write) -> fmt::Result {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        out.write_str(LOG_BUFFER.lock().contents())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn logger(spec: &'static str) -> KernelLogger {
+        let mut logger = KernelLogger {
+            default_level: DEFAULT_LEVEL,
+            directives: [None; MAX_DIRECTIVES],
+        };
+        logger.parse(spec).unwrap();
+        logger
+    }
+
+    #[test_case]
+    fn most_specific_directive_wins() {
+        let logger = logger("warn, memory=debug, memory::paging=trace");
+        assert_eq!(
+            logger.level_for("kernel_with_bootloader::acpi"),
+            LevelFilter::Warn
+        );
+        assert_eq!(
+            logger.level_for("kernel_with_bootloader::memory"),
+            LevelFilter::Debug
+        );
+        assert_eq!(
+            logger.level_for("kernel_with_bootloader::memory::paging"),
+            LevelFilter::Trace
+        );
+        assert_eq!(logger.max_level(), LevelFilter::Trace);
+    }
+
+    #[test_case]
+    fn directives_match_whole_module_names() {
+        let logger = logger("memory=off");
+        assert_eq!(
+            logger.level_for("kernel_with_bootloader::memory_map"),
+            DEFAULT_LEVEL
+        );
+    }
+
+    #[test_case]
+    fn invalid_directive_is_reported() {
+        let mut logger = logger("");
+        assert_eq!(logger.parse("info,memory=loud"), Err("memory=loud"));
+    }
+
+    #[test_case]
+    fn ring_buffer_drops_oldest_lines() {
+        let mut buffer = LogBuffer::new();
+        for i in 0..LOG_BUFFER_SIZE {
+            writeln!(buffer, "line {}", i).unwrap();
+        }
+        let contents = buffer.contents();
+        assert!(contents.starts_with("line "));
+        let last = alloc::format!("line {}\n", LOG_BUFFER_SIZE - 1);
+        assert!(contents.ends_with(last.as_str()));
+        assert!(!contents.contains("line 0\n"));
+    }
+}
@@ -1,25 +1,61 @@
 #![no_std]
 #![no_main]
 #![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
+#![feature(custom_test_frameworks)]
+#![test_runner(crate::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
 
+extern crate alloc;
+
+#[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
     loop {
-        println!("{}", info);
-        hlt();
+        //The writer needs the heap, a panic before that only reaches serial.
+        match unsafe { FRAME_BUFFER_WRITER } {
+            Some(_) => println!("{}", info),
+            None => serial_println!("{}", info),
+        }
+        x86_64::instructions::hlt();
     }
 }
 
+#[cfg(test)]
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    testing::test_panic_handler(info)
+}
+
 use crate::interrupts::init;
 use bootloader_api::config::Mapping;
-use core::{fmt::Write, ptr::NonNull};
+use core::{fmt::Write, ptr::addr_of_mut, ptr::NonNull};
+use task::{Executor, Task};
 use writer::FrameBufferWriter;
-use x86_64::instructions::hlt;
+mod acpi;
+mod allocator;
+mod gdt;
+mod graphics;
 mod interrupts;
+mod keyboard;
+mod logger;
+mod memory;
+mod serial;
+mod shell;
+mod task;
+#[cfg(test)]
+mod testing;
+mod thread;
+mod time;
 mod writer;
 
 pub static mut FRAME_BUFFER: Option<NonNull<[u8]>> = None;
 pub static mut FRAME_BUFFER_WRITER: Option<NonNull<FrameBufferWriter>> = None;
+//FRAME_BUFFER_WRITER points here, the writer has to outlive the entry point.
+static mut WRITER: Option<FrameBufferWriter> = None;
+//The file the bootloader loaded next to the kernel, if any, e.g. a PSF font
+//for the `font` shell command.
+pub static mut RAMDISK: Option<&'static [u8]> = None;
 
 pub static BOOTLOADER_CONFIG: bootloader_api::BootloaderConfig = {
     let mut config = bootloader_api::BootloaderConfig::new_default();
@@ -31,37 +67,71 @@ pub static BOOTLOADER_CONFIG: bootloader_api::BootloaderConfig = {
 bootloader_api::entry_point!(my_entry_point, config = &BOOTLOADER_CONFIG);
 
 //? CA Question A (2)
+//Both also go to COM1 when serial mirroring is on, see serial.rs.
 #[macro_export]
 macro_rules! print {
     ($($stmt:tt)*) => {
         {
             let frame_buffer_writer = unsafe { FRAME_BUFFER_WRITER.as_mut().unwrap().as_mut() };
 
-            write!(frame_buffer_writer, $($stmt)*).unwrap();
+            match format_args!($($stmt)*) {
+                args => {
+                    frame_buffer_writer.write_fmt(args).unwrap();
+                    $crate::serial::_mirror(args);
+                }
+            }
         }
     };
 }
 
 #[macro_export]
 macro_rules! println {
+    () => {
+        $crate::println!("")
+    };
     ($($stmt:tt)*) => {
         {
             let frame_buffer_writer = unsafe { FRAME_BUFFER_WRITER.as_mut().unwrap().as_mut() };
 
-            writeln!(frame_buffer_writer, $($stmt)*).unwrap();
+            match format_args!($($stmt)*) {
+                args => {
+                    frame_buffer_writer.write_fmt(args).unwrap();
+                    frame_buffer_writer.write_str("\n").unwrap();
+                    $crate::serial::_mirror(format_args!("{}\n", args));
+                }
+            }
         }
     };
 }
 
 fn my_entry_point(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
+    logger::init();
+    //The framebuffer writer keeps its text on the heap.
+    allocator::init_heap(
+        &boot_info.memory_regions,
+        boot_info.physical_memory_offset.into_option(),
+    )
+    .expect("heap initialization failed");
+
     unsafe {
         FRAME_BUFFER = Some(NonNull::new_unchecked(
             boot_info.framebuffer.as_mut().unwrap().buffer_mut(),
         ));
-        FRAME_BUFFER_WRITER = Some(NonNull::new_unchecked(&mut FrameBufferWriter::new(
+        let writer = (*addr_of_mut!(WRITER)).insert(FrameBufferWriter::new(
             FRAME_BUFFER.as_mut().unwrap().as_mut(),
             boot_info.framebuffer.as_mut().unwrap().info(),
-        )));
+        ));
+        FRAME_BUFFER_WRITER = Some(NonNull::from(writer));
+    }
+
+    //the bootloader maps it, it stays mapped and unchanged
+    if let Some(addr) = boot_info.ramdisk_addr.into_option() {
+        unsafe {
+            RAMDISK = Some(core::slice::from_raw_parts(
+                addr as *const u8,
+                boot_info.ramdisk_len as usize,
+            ));
+        }
     }
 
     let frame_buffer_writer = unsafe { FRAME_BUFFER_WRITER.as_mut().unwrap().as_mut() };
@@ -74,17 +144,55 @@ fn my_entry_point(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
     )
     .unwrap();
 
-    frame_buffer_writer.set_pos(50, 200);
-    // frame_buffer_writer.set_color([255, 0, 0, 0]);
+    frame_buffer_writer.set_pos(3, 25);
+    frame_buffer_writer.set_color([255, 0, 0, 0]);
 
     print!("Changing the position! ");
     print!("Testing my print!() macro. ");
     println!("Here is another sentence.");
     println!("This should be printed in the next line, to test the println!() macro.");
+    frame_buffer_writer.set_color(FrameBufferWriter::DEFAULT_COLOR);
+
+    memory::init(
+        &boot_info.memory_regions,
+        boot_info.physical_memory_offset.into_option(),
+    );
+    let heap = allocator::heap_phys_range().unwrap();
+    let heap_start =
+        x86_64::VirtAddr::new(boot_info.physical_memory_offset.into_option().unwrap() + heap.start);
+    log::info!(
+        "kernel heap: {:?} -> {:?}",
+        heap_start,
+        memory::translate(heap_start)
+    );
+
+    let (total_frames, free_frames) = memory::frame_stats();
+    log::info!(
+        "physical memory: {} frames total, {} free ({} KiB)",
+        total_frames,
+        free_frames,
+        free_frames * 4
+    );
+
+    match acpi::init(
+        boot_info.rsdp_addr.into_option(),
+        boot_info.physical_memory_offset.into_option(),
+    ) {
+        Ok(info) => acpi::log_summary(info),
+        Err(err) => log::warn!("no usable ACPI tables ({:?})", err),
+    }
 
+    keyboard::init();
+    shell::init();
+    thread::init();
+    time::init(time::DEFAULT_FREQUENCY_HZ);
     init();
 
-    loop {
-        hlt();
-    }
+    #[cfg(test)]
+    test_main();
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(shell::run(executor.spawner())));
+    executor.spawn(Task::new(writer::blink_cursor()));
+    executor.run();
 }
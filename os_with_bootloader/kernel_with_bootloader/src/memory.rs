@@ -0,0 +1,124 @@
+mod frame_allocator;
+mod paging;
+
+pub use frame_allocator::BootInfoFrameAllocator;
+pub use paging::VirtualMemoryManager;
+
+use crate::allocator;
+use bootloader_api::info::MemoryRegions;
+use spin::Mutex;
+use x86_64::{PhysAddr, VirtAddr};
+
+pub static FRAME_ALLOCATOR: Mutex<Option<BootInfoFrameAllocator>> = Mutex::new(None);
+pub static VMM: Mutex<Option<VirtualMemoryManager>> = Mutex::new(None);
+
+//Must run after the heap is up, the allocator keeps its bookkeeping in Vecs.
+pub fn init(memory_regions: &MemoryRegions, physical_memory_offset: Option<u64>) {
+    let frame_allocator = BootInfoFrameAllocator::new(memory_regions, allocator::heap_phys_range());
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+
+    let offset = VirtAddr::new(physical_memory_offset.expect("no physical memory mapping"));
+    let vmm = unsafe { VirtualMemoryManager::new(offset) }.expect("paging initialization failed");
+    *VMM.lock() = Some(vmm);
+}
+
+//Walk the active page tables for `addr`.
+pub fn translate(addr: VirtAddr) -> Option<PhysAddr> {
+    VMM.lock().as_ref()?.translate(addr)
+}
+
+//Returns (total, free) number of 4 KiB frames.
+pub fn frame_stats() -> (usize, usize) {
+    match FRAME_ALLOCATOR.lock().as_ref() {
+        Some(frame_allocator) => (
+            frame_allocator.total_frames(),
+            frame_allocator.free_frames(),
+        ),
+        None => (0, 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use paging::PagingError;
+    use x86_64::structures::paging::mapper::MapToError;
+    use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PageTableFlags};
+
+    fn physical_memory_offset() -> VirtAddr {
+        VMM.lock().as_ref().unwrap().physical_memory_offset()
+    }
+
+    #[test_case]
+    fn heap_translates_through_the_physical_mapping() {
+        let heap = allocator::heap_phys_range().unwrap();
+        let virt = physical_memory_offset() + heap.start;
+        assert_eq!(translate(virt), Some(PhysAddr::new(heap.start)));
+    }
+
+    #[test_case]
+    fn frames_are_returned_on_deallocate() {
+        let (_, free_before) = frame_stats();
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        let frame_allocator = frame_allocator.as_mut().unwrap();
+        let frame = frame_allocator.allocate_frame().unwrap();
+        assert_eq!(frame_allocator.free_frames(), free_before - 1);
+        unsafe { frame_allocator.deallocate_frame(frame) };
+        assert_eq!(frame_allocator.free_frames(), free_before);
+    }
+
+    #[test_case]
+    fn frames_never_overlap_the_heap() {
+        let heap = allocator::heap_phys_range().unwrap();
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        let frame_allocator = frame_allocator.as_mut().unwrap();
+        let frame = frame_allocator.allocate_frame().unwrap();
+        assert!(!heap.contains(&frame.start_address().as_u64()));
+        unsafe { frame_allocator.deallocate_frame(frame) };
+    }
+
+    #[test_case]
+    fn map_and_unmap_range() {
+        //Somewhere in the lower half that nothing maps.
+        let start = VirtAddr::new(0x0000_4444_0000_0000);
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        let (_, free_before) = frame_stats();
+
+        let mut vmm = VMM.lock();
+        let vmm = vmm.as_mut().unwrap();
+        assert_eq!(vmm.translate(start), None);
+        vmm.map_range(start, 2 * 4096, flags).unwrap();
+        let page = start.as_mut_ptr::<u64>();
+        unsafe {
+            page.write_volatile(0xdead_beef);
+            assert_eq!(page.read_volatile(), 0xdead_beef);
+        }
+        assert!(vmm.translate(start).is_some());
+
+        vmm.unmap_range(start, 2 * 4096, true).unwrap();
+        assert_eq!(vmm.translate(start), None);
+        //Page tables created for the range stay allocated, up to three of them.
+        assert!(frame_stats().1 + 3 >= free_before);
+    }
+
+    #[test_case]
+    fn failed_map_range_leaves_nothing_mapped() {
+        let start = VirtAddr::new(0x0000_4444_1000_0000);
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+        let mut vmm = VMM.lock();
+        let vmm = vmm.as_mut().unwrap();
+        //the third page is taken, so mapping the range fails there
+        vmm.map_range(start + 2 * 4096u64, 4096, flags).unwrap();
+        let (_, free_before) = frame_stats();
+        assert!(matches!(
+            vmm.map_range(start, 3 * 4096, flags),
+            Err(PagingError::Map(MapToError::PageAlreadyMapped(_)))
+        ));
+        assert_eq!(vmm.translate(start), None);
+        assert_eq!(vmm.translate(start + 4096u64), None);
+        assert_eq!(frame_stats().1, free_before);
+
+        vmm.unmap_range(start + 2 * 4096u64, 4096, true).unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+use alloc::vec::Vec;
+use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
+use core::ops::Range;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB};
+use x86_64::PhysAddr;
+
+const FRAME_SIZE: u64 = 4096;
+
+//Hands out the 4 KiB frames of all `Usable` regions, front to back. Frames that
+//are given back are kept on a free list and reused before touching new memory.
+pub struct BootInfoFrameAllocator {
+    usable: Vec<Range<u64>>,
+    region: usize,
+    next: u64,
+    free_list: Vec<PhysFrame>,
+    total_frames: usize,
+    allocated_frames: usize,
+}
+
+impl BootInfoFrameAllocator {
+    //`reserved` is a physical range already in use (e.g. the kernel heap) that
+    //must never be handed out.
+    pub fn new(memory_regions: &MemoryRegions, reserved: Option<Range<u64>>) -> Self {
+        let mut usable = Vec::new();
+        for region in memory_regions
+            .iter()
+            .filter(|region| region.kind == MemoryRegionKind::Usable)
+        {
+            // frame 0 stays unused so that a null physical address is never valid
+            let start = align_up(region.start.max(FRAME_SIZE), FRAME_SIZE);
+            let end = align_down(region.end, FRAME_SIZE);
+            match &reserved {
+                Some(reserved) if reserved.start < end && start < reserved.end => {
+                    push_range(&mut usable, start..align_down(reserved.start, FRAME_SIZE));
+                    push_range(&mut usable, align_up(reserved.end, FRAME_SIZE)..end);
+                }
+                _ => push_range(&mut usable, start..end),
+            }
+        }
+
+        let total_frames = usable
+            .iter()
+            .map(|range| ((range.end - range.start) / FRAME_SIZE) as usize)
+            .sum();
+        let next = usable.first().map_or(0, |range| range.start);
+
+        BootInfoFrameAllocator {
+            usable,
+            region: 0,
+            next,
+            free_list: Vec::new(),
+            total_frames,
+            allocated_frames: 0,
+        }
+    }
+
+    pub fn total_frames(&self) -> usize {
+        self.total_frames
+    }
+
+    pub fn free_frames(&self) -> usize {
+        self.total_frames - self.allocated_frames
+    }
+
+    fn next_unused_frame(&mut self) -> Option<PhysFrame> {
+        while let Some(range) = self.usable.get(self.region) {
+            if self.next < range.end {
+                let frame = PhysFrame::containing_address(PhysAddr::new(self.next));
+                self.next += FRAME_SIZE;
+                return Some(frame);
+            }
+            self.region += 1;
+            if let Some(range) = self.usable.get(self.region) {
+                self.next = range.start;
+            }
+        }
+        None
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let frame = self.free_list.pop().or_else(|| self.next_unused_frame())?;
+        self.allocated_frames += 1;
+        Some(frame)
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.free_list.push(frame);
+        self.allocated_frames -= 1;
+    }
+}
+
+fn push_range(ranges: &mut Vec<Range<u64>>, range: Range<u64>) {
+    if range.start < range.end {
+        ranges.push(range);
+    }
+}
+
+fn align_up(addr: u64, align: u64) -> u64 {
+    (addr + align - 1) & !(align - 1)
+}
+
+fn align_down(addr: u64, align: u64) -> u64 {
+    addr & !(align - 1)
+}
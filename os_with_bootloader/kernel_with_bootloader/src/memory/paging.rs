@@ -0,0 +1,218 @@
+use super::FRAME_ALLOCATOR;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::mapper::{
+    FlagUpdateError, MapToError, MapperFlush, TranslateResult, UnmapError,
+};
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags,
+    PhysFrame, Size4KiB, Translate,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+const PAGE_SIZE: u64 = 4096;
+
+//The wrapped errors are only read through Debug, when a caller reports them.
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum PagingError {
+    FrameAllocationFailed,
+    NoFreeAddressSpace,
+    Map(MapToError<Size4KiB>),
+    Unmap(UnmapError),
+    UpdateFlags(FlagUpdateError),
+}
+
+//Safe wrapper around the active level 4 page table. All page tables are
+//reached through the complete physical memory mapping that the bootloader sets
+//up at `physical_memory_offset`.
+pub struct VirtualMemoryManager {
+    mapper: OffsetPageTable<'static>,
+    mmio_next: VirtAddr,
+    mmio_end: VirtAddr,
+}
+
+impl VirtualMemoryManager {
+    //Safety: `physical_memory_offset` must be the start of a mapping of the
+    //complete physical memory, and this may only be created once.
+    pub unsafe fn new(physical_memory_offset: VirtAddr) -> Result<Self, PagingError> {
+        let level_4_table = active_level_4_table(physical_memory_offset);
+
+        //MMIO windows get a level 4 entry of their own so they can never
+        //collide with anything the bootloader mapped.
+        let free_entry = level_4_table
+            .iter()
+            .enumerate()
+            .skip(256) // upper (kernel) half only
+            .find(|(_, entry)| entry.is_unused())
+            .map(|(index, _)| index as u64)
+            .ok_or(PagingError::NoFreeAddressSpace)?;
+        let mmio_start = VirtAddr::new_truncate(free_entry << 39);
+
+        Ok(VirtualMemoryManager {
+            mapper: OffsetPageTable::new(level_4_table, physical_memory_offset),
+            mmio_next: mmio_start,
+            mmio_end: mmio_start + (1u64 << 39),
+        })
+    }
+
+    #[cfg(test)]
+    pub fn physical_memory_offset(&self) -> VirtAddr {
+        self.mapper.phys_offset()
+    }
+
+    //Back every page in `start..start + size` with a freshly allocated frame.
+    //On failure nothing of the range stays mapped.
+    #[allow(dead_code)]
+    pub fn map_range(
+        &mut self,
+        start: VirtAddr,
+        size: u64,
+        flags: PageTableFlags,
+    ) -> Result<(), PagingError> {
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        let frame_allocator = frame_allocator
+            .as_mut()
+            .ok_or(PagingError::FrameAllocationFailed)?;
+
+        for (mapped, page) in pages(start, size).enumerate() {
+            let result = match frame_allocator.allocate_frame() {
+                Some(frame) => {
+                    match unsafe { self.mapper.map_to(page, frame, flags, frame_allocator) } {
+                        Ok(flush) => {
+                            flush.flush();
+                            Ok(())
+                        }
+                        Err(err) => {
+                            unsafe { frame_allocator.deallocate_frame(frame) };
+                            Err(PagingError::Map(err))
+                        }
+                    }
+                }
+                None => Err(PagingError::FrameAllocationFailed),
+            };
+            if let Err(err) = result {
+                //take back the pages mapped so far, the frames were ours
+                for page in pages(start, size).take(mapped) {
+                    if let Ok((frame, flush)) = self.mapper.unmap(page) {
+                        flush.flush();
+                        unsafe { frame_allocator.deallocate_frame(frame) };
+                    }
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    //Map `start..start + size` onto the given physical range, e.g. for MMIO.
+    //The frames are not owned by the frame allocator and are never freed.
+    pub fn map_physical(
+        &mut self,
+        start: VirtAddr,
+        phys: PhysAddr,
+        size: u64,
+        flags: PageTableFlags,
+    ) -> Result<(), PagingError> {
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        let frame_allocator = frame_allocator
+            .as_mut()
+            .ok_or(PagingError::FrameAllocationFailed)?;
+
+        let first_frame = PhysFrame::<Size4KiB>::containing_address(phys);
+        for (i, page) in pages(start, size).enumerate() {
+            let frame = first_frame + i as u64;
+            unsafe { self.mapper.map_to(page, frame, flags, frame_allocator) }
+                .map(MapperFlush::flush)
+                .map_err(PagingError::Map)?;
+        }
+        Ok(())
+    }
+
+    //Map a device's registers into the MMIO window, uncached.
+    pub fn map_mmio(&mut self, phys: PhysAddr, size: u64) -> Result<VirtAddr, PagingError> {
+        let offset = phys.as_u64() % PAGE_SIZE;
+        let size = align_up(offset + size, PAGE_SIZE);
+        let start = self.mmio_next;
+        if start + size > self.mmio_end {
+            return Err(PagingError::NoFreeAddressSpace);
+        }
+
+        let flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::NO_CACHE
+            | PageTableFlags::WRITE_THROUGH;
+        self.map_physical(start, phys.align_down(PAGE_SIZE), size, flags)?;
+        self.mmio_next = start + size;
+        Ok(start + offset)
+    }
+
+    //Remove the mapping of every page in the range. Frames are only returned
+    //to the frame allocator when `free_frames` is set, which must not be done
+    //for ranges created with `map_physical`.
+    #[allow(dead_code)]
+    pub fn unmap_range(
+        &mut self,
+        start: VirtAddr,
+        size: u64,
+        free_frames: bool,
+    ) -> Result<(), PagingError> {
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+
+        for page in pages(start, size) {
+            let (frame, flush) = self.mapper.unmap(page).map_err(PagingError::Unmap)?;
+            flush.flush();
+            if let (true, Some(frame_allocator)) = (free_frames, frame_allocator.as_mut()) {
+                unsafe { frame_allocator.deallocate_frame(frame) };
+            }
+        }
+        Ok(())
+    }
+
+    //Change the protection of an already mapped range.
+    #[allow(dead_code)]
+    pub fn protect(
+        &mut self,
+        start: VirtAddr,
+        size: u64,
+        flags: PageTableFlags,
+    ) -> Result<(), PagingError> {
+        for page in pages(start, size) {
+            unsafe { self.mapper.update_flags(page, flags) }
+                .map(MapperFlush::flush)
+                .map_err(PagingError::UpdateFlags)?;
+        }
+        Ok(())
+    }
+
+    pub fn translate(&self, addr: VirtAddr) -> Option<PhysAddr> {
+        self.mapper.translate_addr(addr)
+    }
+
+    #[allow(dead_code)]
+    pub fn flags(&self, addr: VirtAddr) -> Option<PageTableFlags> {
+        match self.mapper.translate(addr) {
+            TranslateResult::Mapped { flags, .. } => Some(flags),
+            _ => None,
+        }
+    }
+}
+
+unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
+    let (level_4_table_frame, _) = Cr3::read();
+
+    let phys = level_4_table_frame.start_address();
+    let virt = physical_memory_offset + phys.as_u64();
+    let page_table_ptr: *mut PageTable = virt.as_mut_ptr();
+
+    &mut *page_table_ptr
+}
+
+fn pages(start: VirtAddr, size: u64) -> impl Iterator<Item = Page<Size4KiB>> {
+    let first = Page::containing_address(start);
+    let last = Page::containing_address(start + size.max(1) - 1u64);
+    Page::range_inclusive(first, last)
+}
+
+fn align_up(addr: u64, align: u64) -> u64 {
+    (addr + align - 1) & !(align - 1)
+}
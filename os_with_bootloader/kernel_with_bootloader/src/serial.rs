@@ -0,0 +1,64 @@
+use core::fmt;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::lazy_static;
+use spin::Mutex;
+use uart_16550::SerialPort;
+
+const COM1_PORT: u16 = 0x3f8;
+
+lazy_static! {
+    //The port is initialized on first use, so serial output works even before
+    //the framebuffer or the heap are set up.
+    pub static ref SERIAL1: Mutex<SerialPort> = {
+        let mut serial_port = unsafe { SerialPort::new(COM1_PORT) };
+        serial_port.init();
+        Mutex::new(serial_port)
+    };
+}
+
+//When set, print!/println! also go to COM1. Defaults to on with the
+//`serial_mirror` feature so headless runs (CI, tests) can read the output
+//from `-serial stdio`, and can be toggled with the shell's `serial` command.
+static MIRROR: AtomicBool = AtomicBool::new(cfg!(feature = "serial_mirror"));
+
+pub fn set_mirror(enabled: bool) {
+    MIRROR.store(enabled, Ordering::Relaxed);
+}
+
+pub fn mirror_enabled() -> bool {
+    MIRROR.load(Ordering::Relaxed)
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    //An interrupt handler printing while we hold the lock would deadlock.
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        SERIAL1
+            .lock()
+            .write_fmt(args)
+            .expect("printing to serial failed");
+    });
+}
+
+//Used by print!/println!, does nothing unless mirroring is on.
+#[doc(hidden)]
+pub fn _mirror(args: fmt::Arguments) {
+    if mirror_enabled() {
+        _print(args);
+    }
+}
+
+#[macro_export]
+macro_rules! serial_print {
+    ($($arg:tt)*) => {
+        $crate::serial::_print(format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! serial_println {
+    () => ($crate::serial_print!("\n"));
+    ($fmt:expr) => ($crate::serial_print!(concat!($fmt, "\n")));
+    ($fmt:expr, $($arg:tt)*) => ($crate::serial_print!(concat!($fmt, "\n"), $($arg)*));
+}
@@ -0,0 +1,491 @@
+mod commands;
+
+use crate::keyboard::{self, ScancodeStream};
+use crate::print;
+use crate::println;
+use crate::task::Spawner;
+use crate::writer::FrameBufferWriter;
+use crate::FRAME_BUFFER_WRITER;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use futures_util::stream::StreamExt;
+use pc_keyboard::{DecodedKey, KeyCode};
+use spin::{Mutex, Once};
+
+const PROMPT: &str = "kernel> ";
+const HISTORY_SIZE: usize = 32;
+
+//A shell command. `run` gets the words after the command name.
+#[derive(Clone, Copy)]
+pub struct Command {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub run: fn(&[&str]),
+}
+
+static COMMANDS: Mutex<Vec<Command>> = Mutex::new(Vec::new());
+//For commands that start background tasks, set by run().
+static SPAWNER: Once<Spawner> = Once::new();
+
+//Add a command to the shell. Returns false if the name is already taken.
+pub fn register(command: Command) -> bool {
+    let mut commands = COMMANDS.lock();
+    if commands
+        .iter()
+        .any(|existing| existing.name == command.name)
+    {
+        return false;
+    }
+    commands.push(command);
+    true
+}
+
+fn find(name: &str) -> Option<Command> {
+    COMMANDS
+        .lock()
+        .iter()
+        .find(|command| command.name == name)
+        .copied()
+}
+
+//Register the built-in commands. Needs the heap.
+pub fn init() {
+    commands::register_builtins();
+}
+
+fn writer() -> &'static mut FrameBufferWriter {
+    unsafe { FRAME_BUFFER_WRITER.unwrap().as_mut() }
+}
+
+struct Shell {
+    line: Vec<char>,
+    cursor: usize,
+    history: VecDeque<String>,
+    //Entry shown while browsing the history, None when editing a new line.
+    history_index: Option<usize>,
+    //The new line being typed, kept while browsing the history.
+    draft: Vec<char>,
+}
+
+impl Shell {
+    fn new() -> Self {
+        Shell {
+            line: Vec::new(),
+            cursor: 0,
+            history: VecDeque::with_capacity(HISTORY_SIZE),
+            history_index: None,
+            draft: Vec::new(),
+        }
+    }
+
+    fn prompt(&self) {
+        print!("{}", PROMPT);
+    }
+
+    fn handle_key(&mut self, key: DecodedKey) {
+        match key {
+            DecodedKey::Unicode('\n') => self.submit(),
+            DecodedKey::Unicode('\u{0008}') => self.backspace(),
+            DecodedKey::Unicode('\u{007f}') => self.delete(),
+            DecodedKey::Unicode('\t') => self.complete(),
+            DecodedKey::Unicode(character) if !character.is_control() => self.insert(character),
+            DecodedKey::RawKey(KeyCode::ArrowLeft) => self.move_left(1),
+            DecodedKey::RawKey(KeyCode::ArrowRight) => self.move_right(1),
+            DecodedKey::RawKey(KeyCode::Home) => self.move_left(self.cursor),
+            DecodedKey::RawKey(KeyCode::End) => self.move_right(self.line.len() - self.cursor),
+            DecodedKey::RawKey(KeyCode::ArrowUp) => self.history_previous(),
+            DecodedKey::RawKey(KeyCode::ArrowDown) => self.history_next(),
+            DecodedKey::RawKey(KeyCode::PageUp) if keyboard::shift_pressed() => {
+                writer().scroll_back()
+            }
+            DecodedKey::RawKey(KeyCode::PageDown) if keyboard::shift_pressed() => {
+                writer().scroll_forward()
+            }
+            _ => {}
+        }
+    }
+
+    //Print the line from the cursor on, followed by `blanks` spaces to cover
+    //removed characters, and put the screen cursor back.
+    fn redraw_tail(&self, blanks: usize) {
+        let tail: String = self.line[self.cursor..].iter().collect();
+        print!("{}{:blanks$}", tail, "", blanks = blanks);
+        for _ in 0..self.line.len() - self.cursor + blanks {
+            writer().arrow_left();
+        }
+    }
+
+    //Whether the line from the cursor on, plus `extra` characters, ends in the
+    //screen row of the cursor. The writer can then shift it in place.
+    fn tail_fits_row(&self, extra: usize) -> bool {
+        let writer = writer();
+        let (_, column) = writer.cursor();
+        column + self.line.len() - self.cursor + extra <= writer.size().1
+    }
+
+    fn insert(&mut self, character: char) {
+        let shift = self.cursor < self.line.len() && self.tail_fits_row(1);
+        self.line.insert(self.cursor, character);
+        self.cursor += 1;
+        if shift {
+            writer().set_insert_mode(true);
+            print!("{}", character);
+            writer().set_insert_mode(false);
+        } else {
+            print!("{}", character);
+            self.redraw_tail(0);
+        }
+    }
+
+    fn backspace(&mut self) {
+        if self.cursor == 0 {
+            return;
+        }
+        self.cursor -= 1;
+        self.line.remove(self.cursor);
+        writer().backspace();
+        self.redraw_tail(1);
+    }
+
+    fn delete(&mut self) {
+        if self.cursor == self.line.len() {
+            return;
+        }
+        let shift = self.tail_fits_row(0);
+        self.line.remove(self.cursor);
+        if shift {
+            writer().delete_char();
+        } else {
+            self.redraw_tail(1);
+        }
+    }
+
+    fn move_left(&mut self, count: usize) {
+        let count = count.min(self.cursor);
+        for _ in 0..count {
+            writer().arrow_left();
+        }
+        self.cursor -= count;
+    }
+
+    fn move_right(&mut self, count: usize) {
+        let count = count.min(self.line.len() - self.cursor);
+        for _ in 0..count {
+            writer().arrow_right();
+        }
+        self.cursor += count;
+    }
+
+    //Erase what was typed and show `line` instead, cursor at the end.
+    fn replace_line(&mut self, line: Vec<char>) {
+        self.move_right(self.line.len());
+        for _ in 0..self.line.len() {
+            writer().backspace();
+        }
+        self.line = line;
+        self.cursor = self.line.len();
+        print!("{}", self.line.iter().collect::<String>());
+    }
+
+    fn history_previous(&mut self) {
+        let index = match self.history_index {
+            _ if self.history.is_empty() => return,
+            None => {
+                self.draft = self.line.clone();
+                self.history.len() - 1
+            }
+            Some(index) => index.saturating_sub(1),
+        };
+        self.history_index = Some(index);
+        self.replace_line(self.history[index].chars().collect());
+    }
+
+    fn history_next(&mut self) {
+        match self.history_index {
+            Some(index) if index + 1 < self.history.len() => {
+                self.history_index = Some(index + 1);
+                self.replace_line(self.history[index + 1].chars().collect());
+            }
+            Some(_) => {
+                self.history_index = None;
+                let draft = core::mem::take(&mut self.draft);
+                self.replace_line(draft);
+            }
+            None => {}
+        }
+    }
+
+    //Complete the command name under the cursor. With several candidates the
+    //common part is filled in, or the candidates are listed.
+    fn complete(&mut self) {
+        if self.line[..self.cursor].contains(&' ') {
+            return;
+        }
+        let prefix: String = self.line[..self.cursor].iter().collect();
+        let mut candidates: Vec<&'static str> = COMMANDS
+            .lock()
+            .iter()
+            .map(|command| command.name)
+            .filter(|name| name.starts_with(prefix.as_str()))
+            .collect();
+        candidates.sort_unstable();
+
+        let common = match candidates.split_first() {
+            None => return,
+            Some((first, rest)) => rest.iter().fold(*first, |common, name| {
+                //byte offset of the first differing character
+                let end = common
+                    .char_indices()
+                    .zip(name.chars())
+                    .find(|((_, a), b)| a != b)
+                    .map_or(common.len().min(name.len()), |((index, _), _)| index);
+                &common[..end]
+            }),
+        };
+        let missing = common.strip_prefix(prefix.as_str()).unwrap_or("");
+
+        if candidates.len() == 1 {
+            missing
+                .chars()
+                .chain(core::iter::once(' '))
+                .for_each(|character| self.insert(character));
+        } else if !missing.is_empty() {
+            missing.chars().for_each(|character| self.insert(character));
+        } else {
+            let cursor = self.cursor;
+            self.move_right(self.line.len());
+            println!();
+            println!("{}", candidates.join("  "));
+            self.prompt();
+            print!("{}", self.line.iter().collect::<String>());
+            self.move_left(self.line.len() - cursor);
+        }
+    }
+
+    fn submit(&mut self) {
+        self.move_right(self.line.len());
+        println!();
+
+        let line: String = self.line.drain(..).collect();
+        self.cursor = 0;
+        self.history_index = None;
+        self.draft.clear();
+
+        let line = line.trim();
+        if !line.is_empty() {
+            if self.history.back().map(String::as_str) != Some(line) {
+                if self.history.len() == HISTORY_SIZE {
+                    self.history.pop_front();
+                }
+                self.history.push_back(String::from(line));
+            }
+            execute(line);
+        }
+        self.prompt();
+    }
+}
+
+fn execute(line: &str) {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match find(words[0]) {
+        //The table lock is released, commands may use it (help does).
+        Some(command) => (command.run)(&words[1..]),
+        None => println!("{}: command not found, try `help`", words[0]),
+    }
+}
+
+//Kernel task that reads the keyboard and runs commands.
+pub async fn run(spawner: Spawner) {
+    SPAWNER.call_once(|| spawner);
+    let mut scancodes = ScancodeStream::new();
+    let mut shell = Shell::new();
+    shell.prompt();
+
+    while let Some(scancode) = scancodes.next().await {
+        keyboard::report_dropped_scancodes();
+        if let Some(key) = keyboard::decode(scancode) {
+            shell.handle_key(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nothing(_args: &[&str]) {}
+
+    #[test_case]
+    fn command_names_are_unique() {
+        let command = Command {
+            name: "test-unique",
+            help: "",
+            run: nothing,
+        };
+        assert!(register(command));
+        assert!(!register(command));
+        assert!(find("test-unique").is_some());
+    }
+
+    fn type_keys(shell: &mut Shell, keys: &str) {
+        for character in keys.chars() {
+            shell.handle_key(DecodedKey::Unicode(character));
+        }
+    }
+
+    fn press(shell: &mut Shell, key: KeyCode) {
+        shell.handle_key(DecodedKey::RawKey(key));
+    }
+
+    fn line(shell: &Shell) -> String {
+        shell.line.iter().collect()
+    }
+
+    #[test_case]
+    fn keys_edit_the_line_at_the_cursor() {
+        let mut shell = Shell::new();
+        type_keys(&mut shell, "abd");
+        press(&mut shell, KeyCode::ArrowLeft);
+        type_keys(&mut shell, "c");
+        assert_eq!(line(&shell), "abcd");
+        assert_eq!(shell.cursor, 3);
+
+        press(&mut shell, KeyCode::Home);
+        type_keys(&mut shell, "\u{0008}");
+        assert_eq!(line(&shell), "abcd");
+        type_keys(&mut shell, "\u{007f}");
+        assert_eq!(line(&shell), "bcd");
+        assert_eq!(shell.cursor, 0);
+
+        press(&mut shell, KeyCode::ArrowRight);
+        type_keys(&mut shell, "\u{0008}");
+        assert_eq!(line(&shell), "cd");
+        assert_eq!(shell.cursor, 0);
+
+        press(&mut shell, KeyCode::End);
+        assert_eq!(shell.cursor, 2);
+        type_keys(&mut shell, "\u{007f}");
+        press(&mut shell, KeyCode::ArrowRight);
+        assert_eq!(line(&shell), "cd");
+        assert_eq!(shell.cursor, 2);
+        type_keys(&mut shell, "\u{0008}");
+        assert_eq!(line(&shell), "c");
+        assert_eq!(shell.cursor, 1);
+    }
+
+    #[test_case]
+    fn edits_inside_the_line_update_the_screen() {
+        println!();
+        let (row, start) = writer().cursor();
+        let mut shell = Shell::new();
+        type_keys(&mut shell, "abd");
+        press(&mut shell, KeyCode::ArrowLeft);
+        type_keys(&mut shell, "c");
+        press(&mut shell, KeyCode::Home);
+        type_keys(&mut shell, "\u{007f}");
+        let screen: String = (start..start + 4)
+            .map(|column| writer().cell(row, column).unwrap().character)
+            .collect();
+        assert_eq!(screen, "bcd ");
+    }
+
+    #[test_case]
+    fn arrows_browse_the_history() {
+        register(Command {
+            name: "tst-history",
+            help: "",
+            run: nothing,
+        });
+        let mut shell = Shell::new();
+        type_keys(&mut shell, "tst-history one\n");
+        type_keys(&mut shell, "tst-history two\n");
+        type_keys(&mut shell, "  tst-history two \n"); // same as the last entry
+        type_keys(&mut shell, "\n");
+        assert_eq!(shell.history, ["tst-history one", "tst-history two"]);
+        assert!(shell.line.is_empty());
+
+        type_keys(&mut shell, "draft");
+        press(&mut shell, KeyCode::ArrowUp);
+        assert_eq!(line(&shell), "tst-history two");
+        press(&mut shell, KeyCode::ArrowUp);
+        press(&mut shell, KeyCode::ArrowUp);
+        assert_eq!(line(&shell), "tst-history one");
+        assert_eq!(shell.cursor, shell.line.len());
+        press(&mut shell, KeyCode::ArrowDown);
+        assert_eq!(line(&shell), "tst-history two");
+        press(&mut shell, KeyCode::ArrowDown);
+        assert_eq!(line(&shell), "draft");
+        assert_eq!(shell.history_index, None);
+        press(&mut shell, KeyCode::ArrowDown);
+        assert_eq!(line(&shell), "draft");
+    }
+
+    #[test_case]
+    fn the_history_keeps_the_latest_lines() {
+        register(Command {
+            name: "tst-history",
+            help: "",
+            run: nothing,
+        });
+        let mut shell = Shell::new();
+        for number in 0..=HISTORY_SIZE {
+            type_keys(&mut shell, &alloc::format!("tst-history {}\n", number));
+        }
+        assert_eq!(shell.history.len(), HISTORY_SIZE);
+        assert_eq!(shell.history[0], "tst-history 1");
+    }
+
+    #[test_case]
+    fn tab_completes_command_names() {
+        for name in [
+            "tst-complete-alpha",
+            "tst-complete-alps",
+            "tst-complete-beta",
+        ] {
+            register(Command {
+                name,
+                help: "",
+                run: nothing,
+            });
+        }
+
+        let mut shell = Shell::new();
+        type_keys(&mut shell, "tst-complete-b\t");
+        assert_eq!(line(&shell), "tst-complete-beta ");
+
+        //only arguments follow, nothing to complete
+        type_keys(&mut shell, "x\t");
+        assert_eq!(line(&shell), "tst-complete-beta x");
+
+        //several candidates: first the common part, then the list
+        let mut shell = Shell::new();
+        type_keys(&mut shell, "tst-complete-a\t");
+        assert_eq!(line(&shell), "tst-complete-alp");
+        type_keys(&mut shell, "\t");
+        assert_eq!(line(&shell), "tst-complete-alp");
+        assert_eq!(shell.cursor, shell.line.len());
+
+        let mut shell = Shell::new();
+        type_keys(&mut shell, "tst-complete-z\t");
+        assert_eq!(line(&shell), "tst-complete-z");
+    }
+
+    #[test_case]
+    fn tab_completes_names_with_multibyte_characters() {
+        for name in ["tst-ünï-äh", "tst-ünï-öh"] {
+            register(Command {
+                name,
+                help: "",
+                run: nothing,
+            });
+        }
+
+        let mut shell = Shell::new();
+        type_keys(&mut shell, "tst-ü\t");
+        assert_eq!(line(&shell), "tst-ünï-");
+        type_keys(&mut shell, "ö\t");
+        assert_eq!(line(&shell), "tst-ünï-öh ");
+    }
+}
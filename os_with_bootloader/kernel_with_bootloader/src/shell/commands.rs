@@ -0,0 +1,435 @@
+use super::{register, Command, COMMANDS, SPAWNER};
+use crate::acpi;
+use crate::allocator;
+use crate::logger;
+use crate::memory;
+use crate::println;
+use crate::serial;
+use crate::task::Task;
+use crate::thread;
+use crate::time;
+use crate::writer::{Font, PsfError, PsfFont, Rect};
+use crate::{FRAME_BUFFER_WRITER, RAMDISK};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use noto_sans_mono_bitmap::{FontWeight, RasterHeight};
+use x86_64::instructions::port::Port;
+
+const BUILTINS: &[Command] = &[
+    Command {
+        name: "help",
+        help: "list commands, or describe one: help [COMMAND]",
+        run: help,
+    },
+    Command {
+        name: "clear",
+        help: "clear the screen",
+        run: clear,
+    },
+    Command {
+        name: "echo",
+        help: "print the arguments",
+        run: echo,
+    },
+    Command {
+        name: "uptime",
+        help: "time since boot",
+        run: uptime,
+    },
+    Command {
+        name: "alarm",
+        help: "print MESSAGE after SECONDS, in the background: alarm SECONDS [MESSAGE]",
+        run: alarm,
+    },
+    Command {
+        name: "sleep",
+        help: "wait SECONDS before the next prompt: sleep SECONDS",
+        run: sleep,
+    },
+    Command {
+        name: "beep",
+        help: "beep COUNT times on the PC speaker, in the background: beep [COUNT]",
+        run: beep,
+    },
+    Command {
+        name: "threads",
+        help: "run COUNT kernel threads that sleep and report back: threads [COUNT]",
+        run: threads,
+    },
+    Command {
+        name: "meminfo",
+        help: "heap and physical memory usage",
+        run: meminfo,
+    },
+    Command {
+        name: "dmesg",
+        help: "print the kernel log",
+        run: dmesg,
+    },
+    Command {
+        name: "font",
+        help: "show or change the console font: font [light|regular|bold] [16|20|24|32] | font psf",
+        run: font,
+    },
+    Command {
+        name: "demo",
+        help: "draw some shapes in the top right corner, text overwrites them: demo",
+        run: demo,
+    },
+    Command {
+        name: "serial",
+        help: "show or set whether console output is copied to COM1: serial [on|off]",
+        run: serial_mirror,
+    },
+    Command {
+        name: "reboot",
+        help: "restart the machine",
+        run: reboot,
+    },
+];
+
+pub fn register_builtins() {
+    for command in BUILTINS {
+        register(*command);
+    }
+}
+
+fn help(args: &[&str]) {
+    let mut commands: Vec<Command> = COMMANDS.lock().clone();
+    commands.sort_unstable_by_key(|command| command.name);
+    match args.first() {
+        Some(name) => match commands.iter().find(|command| command.name == *name) {
+            Some(command) => println!("{} - {}", command.name, command.help),
+            None => println!("help: no command named {}", name),
+        },
+        None => {
+            for command in commands {
+                println!("  {:10} {}", command.name, command.help);
+            }
+        }
+    }
+}
+
+fn clear(_args: &[&str]) {
+    unsafe { FRAME_BUFFER_WRITER.unwrap().as_mut().clear() };
+}
+
+fn echo(args: &[&str]) {
+    println!("{}", args.join(" "));
+}
+
+fn uptime(_args: &[&str]) {
+    let uptime = time::uptime();
+    let seconds = uptime.as_secs();
+    println!(
+        "up {}:{:02}:{:02}.{:03} ({} ticks at {} Hz)",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        uptime.subsec_millis(),
+        time::ticks(),
+        time::frequency_hz()
+    );
+}
+
+fn alarm(args: &[&str]) {
+    let seconds = match args.first().map(|seconds| seconds.parse::<u64>()) {
+        Some(Ok(seconds)) => seconds,
+        _ => {
+            println!("alarm: expected a number of seconds");
+            return;
+        }
+    };
+    let message = match args.len() {
+        1 => String::from("time is up"),
+        _ => args[1..].join(" "),
+    };
+    let spawner = match SPAWNER.r#try() {
+        Some(spawner) => spawner,
+        None => return,
+    };
+    spawner.spawn(Task::new(async move {
+        time::sleep_async(Duration::from_secs(seconds)).await;
+        println!("alarm: {}", message);
+    }));
+}
+
+fn sleep(args: &[&str]) {
+    match args.first().map(|seconds| seconds.parse::<u64>()) {
+        Some(Ok(seconds)) => time::sleep(Duration::from_secs(seconds)),
+        _ => println!("sleep: expected a number of seconds"),
+    }
+}
+
+const BEEP_HZ: u32 = 880;
+const BEEP_LENGTH: Duration = Duration::from_millis(150);
+const MAX_BEEPS: u64 = 10;
+
+//One beep is switched off by a one-shot timer. For more, a periodic timer
+//toggles the tone and a task stops it after the last beep.
+fn beep(args: &[&str]) {
+    let count = match args.first().map(|count| count.parse::<u64>()) {
+        None => 1,
+        Some(Ok(count)) if (1..=MAX_BEEPS).contains(&count) => count,
+        _ => {
+            println!("beep: expected a count from 1 to {}", MAX_BEEPS);
+            return;
+        }
+    };
+
+    time::pit::speaker_on(BEEP_HZ);
+    if count == 1 {
+        if time::add_oneshot(BEEP_LENGTH, time::pit::speaker_off).is_none() {
+            time::pit::speaker_off();
+            println!("beep: no timer available");
+        }
+        return;
+    }
+
+    let (timer, spawner) = match (
+        time::add_periodic(BEEP_LENGTH, time::pit::speaker_toggle),
+        SPAWNER.r#try(),
+    ) {
+        (Some(timer), Some(spawner)) => (timer, spawner),
+        (timer, _) => {
+            if let Some(timer) = timer {
+                time::cancel(timer);
+            }
+            time::pit::speaker_off();
+            println!("beep: no timer available");
+            return;
+        }
+    };
+    spawner.spawn(Task::new(async move {
+        //each beep is followed by a pause as long
+        time::sleep_async(BEEP_LENGTH * 2 * count as u32).await;
+        time::cancel(timer);
+        time::pit::speaker_off();
+    }));
+}
+
+//Each thread sleeps a tenth of a second longer than the one before, so they
+//report in order.
+fn threads(args: &[&str]) {
+    let count = match args.first().map(|count| count.parse::<u64>()) {
+        None => 3,
+        Some(Ok(count)) if count > 0 => count,
+        _ => {
+            println!("threads: expected a thread count");
+            return;
+        }
+    };
+    let running = Arc::new(AtomicUsize::new(0));
+    for index in 0..count {
+        let thread_running = running.clone();
+        running.fetch_add(1, Ordering::SeqCst);
+        let spawned = thread::spawn(move || {
+            let start = time::uptime();
+            thread::sleep(Duration::from_millis(100 * (index + 1)));
+            if let Some(id) = thread::current_id() {
+                println!(
+                    "{:?} woke up after {} ms",
+                    id,
+                    (time::uptime() - start).as_millis()
+                );
+            }
+            thread_running.fetch_sub(1, Ordering::SeqCst);
+        });
+        if spawned.is_none() {
+            running.fetch_sub(1, Ordering::SeqCst);
+            println!("threads: only {} thread(s) could be started", index);
+            break;
+        }
+    }
+    //the shell's own thread waits, the others keep running
+    while running.load(Ordering::SeqCst) > 0 {
+        thread::yield_now();
+    }
+}
+
+fn meminfo(_args: &[&str]) {
+    if let Some(heap) = allocator::heap_phys_range() {
+        println!(
+            "heap:     {} KiB at physical {:#x}..{:#x}",
+            allocator::HEAP_SIZE / 1024,
+            heap.start,
+            heap.end
+        );
+    }
+    let (total, free) = memory::frame_stats();
+    println!(
+        "frames:   {} total, {} used, {} free",
+        total,
+        total - free,
+        free
+    );
+    println!("physical: {} KiB total, {} KiB free", total * 4, free * 4);
+}
+
+fn dmesg(_args: &[&str]) {
+    let writer = unsafe { FRAME_BUFFER_WRITER.unwrap().as_mut() };
+    let _ = logger::dmesg(writer);
+}
+
+const DEMO_WIDTH: usize = 240;
+const DEMO_HEIGHT: usize = 160;
+
+fn demo(_args: &[&str]) {
+    let writer = unsafe { FRAME_BUFFER_WRITER.unwrap().as_mut() };
+    writer.draw(|canvas| {
+        let left = canvas.width().saturating_sub(DEMO_WIDTH + 16);
+        canvas.set_clip(Rect::new(left, 16, DEMO_WIDTH, DEMO_HEIGHT));
+        //smaller than asked for on a tiny screen
+        let area = canvas.clip();
+        let (x, y) = (area.x as i32, area.y as i32);
+        let (width, height) = (area.width as i32, area.height as i32);
+        let (center_x, center_y) = (x + width / 2, y + height / 2);
+
+        canvas.clear([16, 16, 48, 255]);
+        canvas.fill_polygon(
+            &[
+                (x + 16, y + height - 16),
+                (center_x, y + 16),
+                (x + width - 16, y + height - 16),
+            ],
+            [160, 32, 32, 255],
+        );
+        canvas.ellipse(
+            center_x,
+            center_y,
+            width / 2 - 8,
+            height / 4,
+            [0, 200, 200, 255],
+        );
+        canvas.circle(center_x, center_y, height / 3, [240, 200, 0, 255]);
+        //bigger than the area, only the arcs inside are drawn
+        canvas.circle(x, y + height, width, [0, 160, 0, 255]);
+        canvas.line(x, y, x + width - 1, y + height - 1, [255, 255, 255, 255]);
+        canvas.line(x, y + height - 1, x + width - 1, y, [255, 255, 255, 255]);
+
+        //8x8 checkerboard
+        let pixels: Vec<[u8; 4]> = (0..64)
+            .map(|index| match (index / 8 + index % 8) % 2 {
+                0 => [255, 255, 255, 255],
+                _ => [0, 0, 0, 255],
+            })
+            .collect();
+        canvas.blit(x + 8, y + 8, 8, &pixels);
+        canvas.rect(x, y, width, height, [255, 255, 255, 255]);
+    });
+}
+
+fn font(args: &[&str]) {
+    let writer = unsafe { FRAME_BUFFER_WRITER.unwrap().as_mut() };
+    if args.is_empty() {
+        let (rows, columns) = writer.size();
+        println!("{}, {}x{} cells", writer.font(), columns, rows);
+        return;
+    }
+
+    let font = if args == ["psf"] {
+        let data = match unsafe { RAMDISK } {
+            Some(data) => data,
+            None => {
+                println!("font: no ramdisk, build with KERNEL_RAMDISK=path/to/font.psf");
+                return;
+            }
+        };
+        match PsfFont::parse(data) {
+            Ok(font) => Font::Psf(font),
+            Err(err) => {
+                let reason = match err {
+                    PsfError::BadMagic => "not a PSF font",
+                    PsfError::UnsupportedVersion(_) => "unsupported PSF version",
+                    PsfError::Truncated => "the file is truncated",
+                    PsfError::Empty => "the font has no glyphs",
+                };
+                println!("font: cannot load the ramdisk: {}", reason);
+                return;
+            }
+        }
+    } else {
+        //unchanged parts are taken from the current noto font
+        let (mut weight, mut size) = match writer.font() {
+            Font::Noto { weight, size } => (*weight, *size),
+            Font::Psf(_) => (FontWeight::Regular, RasterHeight::Size16),
+        };
+        for arg in args {
+            match *arg {
+                "light" => weight = FontWeight::Light,
+                "regular" => weight = FontWeight::Regular,
+                "bold" => weight = FontWeight::Bold,
+                "16" => size = RasterHeight::Size16,
+                "20" => size = RasterHeight::Size20,
+                "24" => size = RasterHeight::Size24,
+                "32" => size = RasterHeight::Size32,
+                other => {
+                    println!("font: unknown weight or size {}", other);
+                    return;
+                }
+            }
+        }
+        Font::Noto { weight, size }
+    };
+
+    if !writer.set_font(font) {
+        println!("font: the glyphs do not fit on the screen");
+        return;
+    }
+    let (rows, columns) = writer.size();
+    println!("{}, {}x{} cells", writer.font(), columns, rows);
+}
+
+fn serial_mirror(args: &[&str]) {
+    match args {
+        [] => {}
+        ["on"] => serial::set_mirror(true),
+        ["off"] => serial::set_mirror(false),
+        _ => {
+            println!("serial: expected on or off");
+            return;
+        }
+    }
+    let state = if serial::mirror_enabled() {
+        "on"
+    } else {
+        "off"
+    };
+    println!("serial mirror {}", state);
+}
+
+//Try the ACPI reset register, then the keyboard controller, then a triple
+//fault.
+fn reboot(_args: &[&str]) {
+    log::info!("rebooting");
+    x86_64::instructions::interrupts::disable();
+
+    if let Some(fadt) = acpi::info().and_then(|info| info.fadt.as_ref()) {
+        match fadt.reset_register {
+            //only I/O port reset registers are supported
+            Some(register) if register.address_space == 1 => unsafe {
+                Port::<u8>::new(register.address as u16).write(fadt.reset_value);
+            },
+            _ => {}
+        }
+    }
+
+    //pulse the CPU reset line through the 8042
+    unsafe { Port::<u8>::new(0x64).write(0xfe) };
+
+    //an empty IDT turns the breakpoint into a triple fault
+    let empty_idt = x86_64::structures::DescriptorTablePointer {
+        limit: 0,
+        base: x86_64::VirtAddr::new(0),
+    };
+    unsafe { x86_64::instructions::tables::lidt(&empty_idt) };
+    x86_64::instructions::interrupts::int3();
+
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
@@ -0,0 +1,199 @@
+use super::{Task, TaskId};
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+use crossbeam_queue::ArrayQueue;
+use x86_64::instructions::interrupts;
+
+const TASK_QUEUE_SIZE: usize = 100;
+
+pub struct Executor {
+    tasks: BTreeMap<TaskId, Task>,
+    task_queue: Arc<ArrayQueue<TaskId>>,
+    //Set by a waker that found task_queue full, every task is polled then.
+    wake_overflow: Arc<AtomicBool>,
+    spawn_queue: Arc<ArrayQueue<Task>>,
+    waker_cache: BTreeMap<TaskId, Waker>,
+}
+
+//Handle for starting new tasks from inside running tasks. New tasks are picked
+//up by the executor on its next pass.
+#[derive(Clone)]
+pub struct Spawner {
+    spawn_queue: Arc<ArrayQueue<Task>>,
+}
+
+impl Spawner {
+    pub fn spawn(&self, task: Task) {
+        if self.spawn_queue.push(task).is_err() {
+            panic!("spawn queue full");
+        }
+    }
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Executor {
+            tasks: BTreeMap::new(),
+            task_queue: Arc::new(ArrayQueue::new(TASK_QUEUE_SIZE)),
+            wake_overflow: Arc::new(AtomicBool::new(false)),
+            spawn_queue: Arc::new(ArrayQueue::new(TASK_QUEUE_SIZE)),
+            waker_cache: BTreeMap::new(),
+        }
+    }
+
+    pub fn spawner(&self) -> Spawner {
+        Spawner {
+            spawn_queue: self.spawn_queue.clone(),
+        }
+    }
+
+    pub fn spawn(&mut self, task: Task) {
+        let task_id = task.id;
+        if self.tasks.insert(task.id, task).is_some() {
+            panic!("task with same ID already in tasks");
+        }
+        self.task_queue.push(task_id).expect("task queue full");
+    }
+
+    pub fn run(&mut self) -> ! {
+        loop {
+            self.spawn_new_tasks();
+            self.run_ready_tasks();
+            self.sleep_if_idle();
+        }
+    }
+
+    fn spawn_new_tasks(&mut self) {
+        while let Some(task) = self.spawn_queue.pop() {
+            self.spawn(task);
+        }
+    }
+
+    fn run_ready_tasks(&mut self) {
+        while let Some(task_id) = self.task_queue.pop() {
+            self.poll_task(task_id);
+        }
+        //Some wakeups did not fit in the queue and nobody knows whose they
+        //were. Polling a task that was not woken is harmless.
+        if self.wake_overflow.swap(false, Ordering::AcqRel) {
+            let task_ids: Vec<TaskId> = self.tasks.keys().copied().collect();
+            for task_id in task_ids {
+                self.poll_task(task_id);
+            }
+        }
+    }
+
+    fn poll_task(&mut self, task_id: TaskId) {
+        let Self {
+            tasks,
+            task_queue,
+            wake_overflow,
+            spawn_queue: _,
+            waker_cache,
+        } = self;
+
+        let task = match tasks.get_mut(&task_id) {
+            Some(task) => task,
+            None => return, // task no longer exists
+        };
+        let waker = waker_cache
+            .entry(task_id)
+            .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone(), wake_overflow.clone()));
+        let mut context = Context::from_waker(waker);
+        match task.poll(&mut context) {
+            Poll::Ready(()) => {
+                // task done -> remove it and its cached waker
+                tasks.remove(&task_id);
+                waker_cache.remove(&task_id);
+            }
+            Poll::Pending => {}
+        }
+    }
+
+    //hlt until the next interrupt when there is nothing to do. Interrupts are
+    //disabled around the check so that a wakeup can't slip in between the
+    //check and the hlt.
+    fn sleep_if_idle(&self) {
+        interrupts::disable();
+        if self.task_queue.is_empty()
+            && self.spawn_queue.is_empty()
+            && !self.wake_overflow.load(Ordering::Acquire)
+        {
+            interrupts::enable_and_hlt();
+        } else {
+            interrupts::enable();
+        }
+    }
+}
+
+struct TaskWaker {
+    task_id: TaskId,
+    task_queue: Arc<ArrayQueue<TaskId>>,
+    wake_overflow: Arc<AtomicBool>,
+}
+
+impl TaskWaker {
+    fn new(
+        task_id: TaskId,
+        task_queue: Arc<ArrayQueue<TaskId>>,
+        wake_overflow: Arc<AtomicBool>,
+    ) -> Waker {
+        Waker::from(Arc::new(TaskWaker {
+            task_id,
+            task_queue,
+            wake_overflow,
+        }))
+    }
+
+    //Called from the keyboard and timer interrupt handlers, so a full queue
+    //must not panic.
+    fn wake_task(&self) {
+        if self.task_queue.push(self.task_id).is_err() {
+            self.wake_overflow.store(true, Ordering::Release);
+        }
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_task();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_task();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::future::poll_fn;
+    use core::sync::atomic::AtomicUsize;
+
+    #[test_case]
+    fn wakes_that_do_not_fit_the_queue_still_poll() {
+        let mut executor = Executor::new();
+        let polls = Arc::new(AtomicUsize::new(0));
+        let counter = polls.clone();
+        executor.spawn(Task::new(poll_fn(move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            Poll::Pending
+        })));
+        executor.run_ready_tasks();
+        assert_eq!(polls.load(Ordering::Relaxed), 1);
+
+        let waker = executor.waker_cache.values().next().unwrap().clone();
+        //ids of tasks that do not exist, they are skipped
+        while executor.task_queue.push(TaskId(u64::MAX)).is_ok() {}
+        waker.wake_by_ref();
+        assert!(executor.wake_overflow.load(Ordering::Relaxed));
+
+        executor.run_ready_tasks();
+        assert_eq!(polls.load(Ordering::Relaxed), 2);
+        assert!(!executor.wake_overflow.load(Ordering::Relaxed));
+    }
+}
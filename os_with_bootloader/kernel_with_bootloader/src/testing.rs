@@ -0,0 +1,58 @@
+//Runner for `#[test_case]` functions. The kernel boots normally, then runs
+//every test and reports over COM1, which the os_with_bootloader test harness
+//reads from `-serial stdio`. QEMU is left through the isa-debug-exit device.
+use crate::{serial_print, serial_println};
+use x86_64::instructions::port::Port;
+
+const ISA_DEBUG_EXIT_PORT: u16 = 0xf4;
+
+//QEMU exits with status (code << 1) | 1, so 33 means success.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum QemuExitCode {
+    Success = 0x10,
+    Failed = 0x11,
+}
+
+//Needs `-device isa-debug-exit,iobase=0xf4,iosize=0x04`, without it this
+//returns and the caller has to halt.
+pub fn exit_qemu(exit_code: QemuExitCode) {
+    unsafe {
+        Port::new(ISA_DEBUG_EXIT_PORT).write(exit_code as u32);
+    }
+}
+
+pub trait Testable {
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        serial_print!("{}...\t", core::any::type_name::<T>());
+        self();
+        serial_println!("[ok]");
+    }
+}
+
+pub fn test_runner(tests: &[&dyn Testable]) -> ! {
+    serial_println!("Running {} tests", tests.len());
+    for test in tests {
+        test.run();
+    }
+    exit_qemu(QemuExitCode::Success);
+    halt()
+}
+
+pub fn test_panic_handler(info: &core::panic::PanicInfo) -> ! {
+    serial_println!("[failed]\n");
+    serial_println!("Error: {}\n", info);
+    exit_qemu(QemuExitCode::Failed);
+    halt()
+}
+
+fn halt() -> ! {
+    loop {
+        x86_64::instructions::interrupts::disable();
+        x86_64::instructions::hlt();
+    }
+}
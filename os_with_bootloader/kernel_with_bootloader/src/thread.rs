@@ -0,0 +1,335 @@
+use crate::time;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+
+mod switch;
+
+//The run queues are allocated once with this capacity. Nothing may allocate
+//while the scheduler lock is held: the heap lock could belong to a thread
+//that was preempted and can't run again until the lock is released.
+const MAX_THREADS: usize = 64;
+const STACK_SIZE: usize = 64 * 1024; // 64 KiB
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Runnable,
+    Sleeping(u64), // until this tick
+    Exited,
+}
+
+//fxsave64/fxrstor64 area
+#[repr(C, align(16))]
+pub struct FpuState([u8; 512]);
+
+pub struct Thread {
+    id: ThreadId,
+    rsp: u64,
+    fpu: Box<FpuState>,
+    state: State,
+    idle: bool,
+    _stack: Option<Vec<u8>>, // None for the boot thread, which keeps its stack
+}
+
+impl Thread {
+    fn new(stack: Option<Vec<u8>>, idle: bool) -> Box<Thread> {
+        let mut fpu = Box::new(FpuState([0; 512]));
+        // start from the creator's FPU/SSE control state
+        switch::save_fpu(&mut fpu);
+        Box::new(Thread {
+            id: ThreadId::new(),
+            rsp: 0,
+            fpu,
+            state: State::Runnable,
+            idle,
+            _stack: stack,
+        })
+    }
+
+    fn spawn(entry: Box<dyn FnOnce() + Send>, idle: bool) -> Box<Thread> {
+        let mut stack = vec![0u8; STACK_SIZE];
+        let entry = Box::into_raw(Box::new(entry)) as u64;
+        let rsp = switch::init_stack(&mut stack, entry);
+        let mut thread = Thread::new(Some(stack), idle);
+        thread.rsp = rsp;
+        thread
+    }
+}
+
+struct Scheduler {
+    current: Box<Thread>,
+    ready: VecDeque<Box<Thread>>,
+    sleeping: Vec<Box<Thread>>,
+    exited: Vec<Box<Thread>>,
+    idle: Option<Box<Thread>>,
+    count: usize,
+}
+
+impl Scheduler {
+    fn wake_sleepers(&mut self, now: u64) {
+        let mut i = 0;
+        while i < self.sleeping.len() {
+            match self.sleeping[i].state {
+                State::Sleeping(until) if until > now => i += 1,
+                _ => {
+                    let mut thread = self.sleeping.swap_remove(i);
+                    thread.state = State::Runnable;
+                    self.ready.push_back(thread);
+                }
+            }
+        }
+    }
+
+    //Round robin. Returns the threads to switch between, or None if the
+    //current thread keeps running.
+    fn switch_next(&mut self) -> Option<(*mut Thread, *const Thread)> {
+        self.wake_sleepers(time::ticks());
+
+        let next = match self.ready.pop_front() {
+            Some(next) => next,
+            None if self.current.state == State::Runnable => return None,
+            None => self.idle.take()?,
+        };
+
+        let mut previous = mem::replace(&mut self.current, next);
+        let previous_ptr: *mut Thread = &mut *previous;
+        match previous.state {
+            State::Runnable if previous.idle => self.idle = Some(previous),
+            State::Runnable => self.ready.push_back(previous),
+            State::Sleeping(_) => self.sleeping.push(previous),
+            State::Exited => {
+                self.count -= 1;
+                self.exited.push(previous);
+            }
+        }
+        Some((previous_ptr, &*self.current))
+    }
+}
+
+static SCHEDULER: Mutex<Option<Scheduler>> = Mutex::new(None);
+
+fn enable_sse() {
+    unsafe {
+        let mut cr0 = Cr0::read();
+        cr0.remove(Cr0Flags::EMULATE_COPROCESSOR | Cr0Flags::TASK_SWITCHED);
+        cr0.insert(Cr0Flags::MONITOR_COPROCESSOR);
+        Cr0::write(cr0);
+
+        let mut cr4 = Cr4::read();
+        cr4.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE);
+        Cr4::write(cr4);
+    }
+}
+
+//Turns the code that is running right now into the first thread. Needs the
+//heap and has to run before the timer interrupt is enabled.
+pub fn init() {
+    enable_sse();
+
+    let idle = Thread::spawn(
+        Box::new(|| loop {
+            interrupts::enable_and_hlt();
+        }),
+        true,
+    );
+    *SCHEDULER.lock() = Some(Scheduler {
+        current: Thread::new(None, false),
+        ready: VecDeque::with_capacity(MAX_THREADS),
+        sleeping: Vec::with_capacity(MAX_THREADS),
+        exited: Vec::with_capacity(MAX_THREADS),
+        idle: Some(idle),
+        count: 1,
+    });
+}
+
+//Free the stacks of exited threads. The Boxes are dropped after the lock is
+//released, and the replacement Vec is allocated before it is taken.
+fn reap() {
+    let spare = Vec::with_capacity(MAX_THREADS);
+    let exited = match SCHEDULER.lock().as_mut() {
+        Some(scheduler) => mem::replace(&mut scheduler.exited, spare),
+        None => return,
+    };
+    drop(exited);
+}
+
+//Start a kernel thread. It runs until `f` returns or it calls exit().
+pub fn spawn<F>(f: F) -> Option<ThreadId>
+where
+    F: FnOnce() + Send + 'static,
+{
+    reap();
+    let thread = Thread::spawn(Box::new(f), false);
+    let id = thread.id;
+
+    let mut scheduler = SCHEDULER.lock();
+    let scheduler = scheduler.as_mut()?;
+    if scheduler.count >= MAX_THREADS {
+        return None;
+    }
+    scheduler.count += 1;
+    scheduler.ready.push_back(thread);
+    Some(id)
+}
+
+pub fn current_id() -> Option<ThreadId> {
+    interrupts::without_interrupts(|| SCHEDULER.lock().as_ref().map(|s| s.current.id))
+}
+
+fn schedule(from_interrupt: bool) {
+    let threads = {
+        let mut scheduler = if from_interrupt {
+            match SCHEDULER.try_lock() {
+                Some(scheduler) => scheduler,
+                None => return, // a thread is in the middle of a scheduler call
+            }
+        } else {
+            SCHEDULER.lock()
+        };
+        match scheduler.as_mut() {
+            Some(scheduler) => scheduler.switch_next(),
+            None => None,
+        }
+    };
+
+    if let Some((old, new)) = threads {
+        unsafe { switch::switch_to(old, new) };
+    }
+}
+
+//Called from the timer interrupt after the EOI was sent.
+pub(crate) fn preempt() {
+    schedule(true);
+}
+
+fn set_current_state(state: State) {
+    if let Some(scheduler) = SCHEDULER.lock().as_mut() {
+        scheduler.current.state = state;
+    }
+}
+
+pub fn yield_now() {
+    interrupts::without_interrupts(|| schedule(false));
+}
+
+//Block the current thread for at least `duration`.
+pub fn sleep(duration: Duration) {
+    let ticks = time::duration_to_ticks(duration);
+    interrupts::without_interrupts(|| {
+        set_current_state(State::Sleeping(time::ticks() + ticks));
+        schedule(false);
+    });
+}
+
+pub fn exit() -> ! {
+    interrupts::disable();
+    set_current_state(State::Exited);
+    schedule(false);
+    unreachable!("exited thread was scheduled again");
+}
+
+extern "C" fn thread_start(entry: *mut Box<dyn FnOnce() + Send>) -> ! {
+    interrupts::enable();
+    let entry = unsafe { Box::from_raw(entry) };
+    entry();
+    exit();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicBool, AtomicUsize};
+
+    //Yield until `done` or fail after a few seconds.
+    fn wait_for(done: impl Fn() -> bool) {
+        let deadline = time::ticks() + time::duration_to_ticks(Duration::from_secs(5));
+        while !done() {
+            assert!(time::ticks() < deadline, "timed out");
+            yield_now();
+        }
+    }
+
+    fn thread_count() -> usize {
+        interrupts::without_interrupts(|| SCHEDULER.lock().as_ref().unwrap().count)
+    }
+
+    #[test_case]
+    fn spawned_threads_run_and_exit() {
+        let counters = [Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0))];
+        for counter in counters.iter().cloned() {
+            spawn(move || {
+                for _ in 0..3 {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    yield_now();
+                }
+            })
+            .unwrap();
+        }
+        assert_eq!(thread_count(), 3);
+
+        wait_for(|| counters.iter().all(|c| c.load(Ordering::SeqCst) == 3));
+        wait_for(|| thread_count() == 1);
+        reap();
+        let scheduler = SCHEDULER.lock();
+        let scheduler = scheduler.as_ref().unwrap();
+        assert!(scheduler.exited.is_empty());
+        assert!(scheduler.ready.is_empty());
+    }
+
+    #[test_case]
+    fn sleeping_thread_waits_for_its_ticks() {
+        let woke_at = Arc::new(AtomicU64::new(0));
+        let duration = Duration::from_millis(30);
+        let start = time::ticks();
+        let thread_woke_at = woke_at.clone();
+        spawn(move || {
+            sleep(duration);
+            thread_woke_at.store(time::ticks(), Ordering::SeqCst);
+        })
+        .unwrap();
+        wait_for(|| woke_at.load(Ordering::SeqCst) != 0);
+        assert!(woke_at.load(Ordering::SeqCst) >= start + time::duration_to_ticks(duration));
+        wait_for(|| thread_count() == 1);
+    }
+
+    //Neither thread yields, only the timer interrupt can switch between them.
+    #[test_case]
+    fn busy_threads_are_preempted() {
+        let started = Arc::new(AtomicBool::new(false));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (thread_started, thread_stop) = (started.clone(), stop.clone());
+        spawn(move || {
+            thread_started.store(true, Ordering::SeqCst);
+            while !thread_stop.load(Ordering::SeqCst) {
+                core::hint::spin_loop();
+            }
+        })
+        .unwrap();
+
+        let deadline = time::ticks() + time::duration_to_ticks(Duration::from_secs(5));
+        while !started.load(Ordering::SeqCst) {
+            assert!(time::ticks() < deadline, "never preempted");
+            core::hint::spin_loop();
+        }
+        stop.store(true, Ordering::SeqCst);
+        wait_for(|| thread_count() == 1);
+    }
+}
@@ -0,0 +1,82 @@
+use super::{FpuState, Thread};
+use core::arch::global_asm;
+
+//switch_context(old_rsp: *mut u64, new_rsp: u64, old_fpu: *mut FpuState, new_fpu: *const FpuState)
+//
+//Saves the callee-saved registers, RFLAGS and the FPU/SSE state of the
+//running thread on its own stack and in `old_fpu`, then loads the same from
+//the new thread. Everything else was already saved by the caller (or by the
+//x86-interrupt prologue when we come from the timer).
+global_asm!(
+    ".global switch_context",
+    "switch_context:",
+    "fxsave64 [rdx]",
+    "push rbp",
+    "push rbx",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "pushfq",
+    "mov [rdi], rsp",
+    "mov rsp, rsi",
+    "fxrstor64 [rcx]",
+    "popfq",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop rbx",
+    "pop rbp",
+    "ret",
+    "",
+    //First "return" of a new thread. r12 holds the boxed entry closure.
+    ".global thread_trampoline",
+    "thread_trampoline:",
+    "mov rdi, r12",
+    "call {thread_start}",
+    "ud2",
+    thread_start = sym super::thread_start,
+);
+
+extern "C" {
+    fn switch_context(
+        old_rsp: *mut u64,
+        new_rsp: u64,
+        old_fpu: *mut FpuState,
+        new_fpu: *const FpuState,
+    );
+    fn thread_trampoline();
+}
+
+//Stack layout expected by the restore half of switch_context.
+pub fn init_stack(stack: &mut [u8], entry: u64) -> u64 {
+    let top = (stack.as_mut_ptr() as u64 + stack.len() as u64) & !0xf;
+    let frame: [u64; 10] = [
+        0x2,   // rflags, interrupts stay off until thread_start
+        0,     // r15
+        0,     // r14
+        0,     // r13
+        entry, // r12
+        0,     // rbx
+        0,     // rbp
+        thread_trampoline as *const () as u64,
+        0, // keeps rsp 16 byte aligned after the ret
+        0,
+    ];
+    let rsp = top - core::mem::size_of_val(&frame) as u64;
+    unsafe { core::ptr::write(rsp as *mut [u64; 10], frame) };
+    rsp
+}
+
+//Safety: interrupts must be disabled and both threads must stay alive (not be
+//reaped) until the switch has completed.
+pub unsafe fn switch_to(old: *mut Thread, new: *const Thread) {
+    switch_context(&mut (*old).rsp, (*new).rsp, &mut *(*old).fpu, &*(*new).fpu);
+}
+
+pub fn save_fpu(fpu: &mut FpuState) {
+    unsafe {
+        core::arch::asm!("fxsave64 [{}]", in(reg) fpu as *mut FpuState, options(nostack));
+    }
+}
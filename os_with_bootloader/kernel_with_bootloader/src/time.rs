@@ -0,0 +1,241 @@
+use crate::thread;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll};
+use core::time::Duration;
+use futures_util::stream::Stream;
+use futures_util::task::AtomicWaker;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+pub mod pit;
+mod timer_wheel;
+
+pub use timer_wheel::TimerId;
+use timer_wheel::{Action, TimerWheel};
+
+pub const DEFAULT_FREQUENCY_HZ: u32 = 100;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+//Number of timer interrupts since interrupts were enabled.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+static UPTIME_NANOS: AtomicU64 = AtomicU64::new(0);
+//Until init() reprograms the PIT it runs at the BIOS default of ~18.2 Hz.
+static NANOS_PER_TICK: AtomicU64 =
+    AtomicU64::new(NANOS_PER_SEC * 0x10000 / pit::BASE_FREQUENCY_HZ as u64);
+static TICK_WAKER: AtomicWaker = AtomicWaker::new();
+static TIMER_WHEEL: Mutex<Option<TimerWheel>> = Mutex::new(None);
+
+#[derive(Debug)]
+pub enum TimerError {
+    //Ticks come from the Local APIC timer, which is calibrated once when the
+    //APIC is set up and can't be changed afterwards.
+    ApicTimer,
+}
+
+//Program the PIT and set up the timer wheel. Needs the heap.
+pub fn init(frequency_hz: u32) {
+    *TIMER_WHEEL.lock() = Some(TimerWheel::new(ticks()));
+    if let Err(err) = set_frequency(frequency_hz) {
+        log::warn!("timer frequency not changed ({:?})", err);
+    }
+}
+
+//Reprogram the PIT, only while it is the one driving the ticks.
+pub fn set_frequency(frequency_hz: u32) -> Result<(), TimerError> {
+    if crate::interrupts::apic_enabled() {
+        return Err(TimerError::ApicTimer);
+    }
+    let actual = pit::set_frequency(frequency_hz);
+    set_tick_frequency(actual);
+    Ok(())
+}
+
+//For when the ticks come from somewhere else than the PIT, e.g. the APIC timer.
+pub(crate) fn set_tick_frequency(frequency_hz: u32) {
+    NANOS_PER_TICK.store(
+        NANOS_PER_SEC / frequency_hz.max(1) as u64,
+        Ordering::Relaxed,
+    );
+}
+
+pub fn frequency_hz() -> u64 {
+    NANOS_PER_SEC / NANOS_PER_TICK.load(Ordering::Relaxed)
+}
+
+//Called by the timer interrupt handler.
+pub(crate) fn tick() {
+    let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    UPTIME_NANOS.fetch_add(NANOS_PER_TICK.load(Ordering::Relaxed), Ordering::Relaxed);
+    TICK_WAKER.wake();
+
+    //If a thread is changing the wheel right now, the expired timers are
+    //picked up on the next tick instead.
+    if let Some(mut wheel) = TIMER_WHEEL.try_lock() {
+        if let Some(wheel) = wheel.as_mut() {
+            wheel.advance(now);
+        }
+    }
+}
+
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+//Monotonic time since the timer interrupt was enabled.
+pub fn uptime() -> Duration {
+    Duration::from_nanos(UPTIME_NANOS.load(Ordering::Relaxed))
+}
+
+//Whole ticks covering at least `duration`.
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    ticks_covering(duration, NANOS_PER_TICK.load(Ordering::Relaxed))
+}
+
+fn ticks_covering(duration: Duration, nanos_per_tick: u64) -> u64 {
+    let nanos_per_tick = nanos_per_tick as u128;
+    ((duration.as_nanos() + nanos_per_tick - 1) / nanos_per_tick) as u64
+}
+
+//Block the calling kernel thread for at least `duration`.
+pub fn sleep(duration: Duration) {
+    thread::sleep(duration);
+}
+
+//Future version of sleep() for async tasks.
+pub fn sleep_async(duration: Duration) -> Sleep {
+    Sleep {
+        deadline: ticks() + duration_to_ticks(duration),
+        timer: None,
+    }
+}
+
+pub struct Sleep {
+    deadline: u64,
+    timer: Option<TimerId>,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if ticks() >= self.deadline {
+            return Poll::Ready(());
+        }
+
+        let deadline = self.deadline;
+        let waker = cx.waker().clone();
+        let timer = self.timer;
+        let armed = interrupts::without_interrupts(|| {
+            let mut wheel = TIMER_WHEEL.lock();
+            let wheel = wheel.as_mut()?;
+            match timer {
+                Some(id) if wheel.update_waker(id, &waker) => Some(id),
+                _ => wheel.insert(deadline, None, Action::Wake(waker)),
+            }
+        });
+
+        match armed {
+            Some(id) => self.timer = Some(id),
+            // no timer available, poll again as soon as possible
+            None => cx.waker().wake_by_ref(),
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(id) = self.timer.take() {
+            cancel(id);
+        }
+    }
+}
+
+fn add_timer(delay: Duration, period: Option<Duration>, callback: fn()) -> Option<TimerId> {
+    let deadline = ticks() + duration_to_ticks(delay);
+    let period = period.map(duration_to_ticks);
+    interrupts::without_interrupts(|| {
+        TIMER_WHEEL
+            .lock()
+            .as_mut()?
+            .insert(deadline, period, Action::Callback(callback))
+    })
+}
+
+//Run `callback` once after `delay`. Callbacks run inside the timer interrupt,
+//so they must be short and must not allocate or block.
+pub fn add_oneshot(delay: Duration, callback: fn()) -> Option<TimerId> {
+    add_timer(delay, None, callback)
+}
+
+//Run `callback` every `period`, see add_oneshot().
+pub fn add_periodic(period: Duration, callback: fn()) -> Option<TimerId> {
+    add_timer(period, Some(period), callback)
+}
+
+pub fn cancel(id: TimerId) -> bool {
+    let removed =
+        interrupts::without_interrupts(|| TIMER_WHEEL.lock().as_mut().and_then(|w| w.remove(id)));
+    removed.is_some()
+}
+
+//Yields the current tick count whenever at least one timer interrupt happened
+//since the last poll. Ticks in between are coalesced, not queued. Only one
+//task can wait on it at a time.
+pub struct TickStream {
+    last_seen: u64,
+}
+
+impl TickStream {
+    pub fn new() -> Self {
+        TickStream { last_seen: ticks() }
+    }
+}
+
+impl Stream for TickStream {
+    type Item = u64;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u64>> {
+        let now = ticks();
+        if now != self.last_seen {
+            self.last_seen = now;
+            return Poll::Ready(Some(now));
+        }
+
+        TICK_WAKER.register(cx.waker());
+        let now = ticks();
+        if now != self.last_seen {
+            TICK_WAKER.take();
+            self.last_seen = now;
+            Poll::Ready(Some(now))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn durations_round_up_to_whole_ticks() {
+        let tick = Duration::from_millis(10);
+        let nanos_per_tick = tick.as_nanos() as u64;
+        assert_eq!(ticks_covering(Duration::ZERO, nanos_per_tick), 0);
+        assert_eq!(ticks_covering(Duration::from_nanos(1), nanos_per_tick), 1);
+        assert_eq!(ticks_covering(tick, nanos_per_tick), 1);
+        assert_eq!(
+            ticks_covering(tick + Duration::from_nanos(1), nanos_per_tick),
+            2
+        );
+        assert_eq!(
+            ticks_covering(tick * 3 - Duration::from_nanos(1), nanos_per_tick),
+            3
+        );
+        assert_eq!(ticks_covering(Duration::from_secs(1), nanos_per_tick), 100);
+    }
+}
@@ -0,0 +1,94 @@
+use x86_64::instructions::port::Port;
+
+//The PIT counts down from the divisor at this rate.
+pub const BASE_FREQUENCY_HZ: u32 = 1_193_182;
+
+const CHANNEL_0_PORT: u16 = 0x40;
+const COMMAND_PORT: u16 = 0x43;
+//channel 0, lobyte/hibyte access, mode 3 (square wave), binary
+const CHANNEL_0_SQUARE_WAVE: u8 = 0b0011_0110;
+
+//Program channel 0 (IRQ 0) for roughly `frequency_hz` interrupts per second.
+//Returns the frequency that was actually set, after rounding the divisor.
+pub fn set_frequency(frequency_hz: u32) -> u32 {
+    let divisor = (BASE_FREQUENCY_HZ / frequency_hz.max(1)).clamp(1, 0xffff);
+
+    let mut command: Port<u8> = Port::new(COMMAND_PORT);
+    let mut channel_0: Port<u8> = Port::new(CHANNEL_0_PORT);
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        command.write(CHANNEL_0_SQUARE_WAVE);
+        channel_0.write((divisor & 0xff) as u8);
+        channel_0.write((divisor >> 8) as u8);
+    });
+
+    BASE_FREQUENCY_HZ / divisor
+}
+
+const CHANNEL_2_PORT: u16 = 0x42;
+const SPEAKER_PORT: u16 = 0x61;
+//channel 2, lobyte/hibyte access, mode 0 (interrupt on terminal count), binary
+const CHANNEL_2_ONE_SHOT: u8 = 0b1011_0000;
+//channel 2, lobyte/hibyte access, mode 3 (square wave), binary
+const CHANNEL_2_SQUARE_WAVE: u8 = 0b1011_0110;
+//speaker port bits: channel 2 gate, and channel 2 output to the speaker
+const SPEAKER_GATE: u8 = 0b01;
+const SPEAKER_DATA: u8 = 0b10;
+
+//Busy-wait for `ms` milliseconds (at most ~54) with channel 2, which is only
+//wired to the PC speaker gate. Works with interrupts disabled, so it can be
+//used to calibrate other timers before the timer interrupt is running.
+pub fn busy_wait_ms(ms: u32) {
+    let count = (BASE_FREQUENCY_HZ * ms.min(54) / 1000).max(1);
+
+    let mut command: Port<u8> = Port::new(COMMAND_PORT);
+    let mut channel_2: Port<u8> = Port::new(CHANNEL_2_PORT);
+    let mut speaker: Port<u8> = Port::new(SPEAKER_PORT);
+    unsafe {
+        // gate on, speaker output off
+        let gate = (speaker.read() & !0b10) | 0b1;
+        speaker.write(gate & !0b1);
+        command.write(CHANNEL_2_ONE_SHOT);
+        channel_2.write((count & 0xff) as u8);
+        channel_2.write((count >> 8) as u8);
+        speaker.write(gate); // rising edge on the gate starts the count
+
+        // bit 5 goes high once the counter reached zero
+        while speaker.read() & 0b10_0000 == 0 {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+//Sound the PC speaker at roughly `frequency_hz` until speaker_off().
+pub fn speaker_on(frequency_hz: u32) {
+    let divisor = (BASE_FREQUENCY_HZ / frequency_hz.max(1)).clamp(1, 0xffff);
+
+    let mut command: Port<u8> = Port::new(COMMAND_PORT);
+    let mut channel_2: Port<u8> = Port::new(CHANNEL_2_PORT);
+    let mut speaker: Port<u8> = Port::new(SPEAKER_PORT);
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        command.write(CHANNEL_2_SQUARE_WAVE);
+        channel_2.write((divisor & 0xff) as u8);
+        channel_2.write((divisor >> 8) as u8);
+        let bits = speaker.read();
+        speaker.write(bits | SPEAKER_GATE | SPEAKER_DATA);
+    });
+}
+
+pub fn speaker_off() {
+    let mut speaker: Port<u8> = Port::new(SPEAKER_PORT);
+    unsafe {
+        let bits = speaker.read();
+        speaker.write(bits & !(SPEAKER_GATE | SPEAKER_DATA));
+    }
+}
+
+//Silence or unsilence the tone started by speaker_on(). Short enough for a
+//timer callback.
+pub fn speaker_toggle() {
+    let mut speaker: Port<u8> = Port::new(SPEAKER_PORT);
+    unsafe {
+        let bits = speaker.read();
+        speaker.write(bits ^ SPEAKER_DATA);
+    }
+}
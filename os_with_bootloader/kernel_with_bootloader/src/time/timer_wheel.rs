@@ -0,0 +1,243 @@
+use alloc::vec::Vec;
+use core::task::Waker;
+
+//Hashed timing wheel: a timer lives in slot `deadline % WHEEL_SLOTS` and is
+//only looked at when that slot comes around. Every slot is allocated for
+//MAX_TIMERS entries up front, because timers are fired and re-armed from the
+//timer interrupt, where we can't allocate.
+const WHEEL_SLOTS: usize = 64;
+pub const MAX_TIMERS: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerId(u64);
+
+pub enum Action {
+    //Runs in interrupt context, so it has to be short and must not allocate.
+    Callback(fn()),
+    Wake(Waker),
+}
+
+struct Timer {
+    id: TimerId,
+    deadline: u64,
+    period: Option<u64>,
+    action: Action,
+}
+
+pub struct TimerWheel {
+    slots: Vec<Vec<Timer>>,
+    count: usize,
+    next_id: u64,
+    last_processed: u64,
+}
+
+impl TimerWheel {
+    pub fn new(now: u64) -> Self {
+        let mut slots = Vec::with_capacity(WHEEL_SLOTS);
+        for _ in 0..WHEEL_SLOTS {
+            slots.push(Vec::with_capacity(MAX_TIMERS));
+        }
+        TimerWheel {
+            slots,
+            count: 0,
+            next_id: 0,
+            last_processed: now,
+        }
+    }
+
+    pub fn insert(
+        &mut self,
+        deadline: u64,
+        period: Option<u64>,
+        action: Action,
+    ) -> Option<TimerId> {
+        if self.count >= MAX_TIMERS {
+            return None;
+        }
+        let id = TimerId(self.next_id);
+        self.next_id += 1;
+        self.count += 1;
+        // never schedule into a slot that was already processed
+        let deadline = deadline.max(self.last_processed + 1);
+        self.slots[deadline as usize % WHEEL_SLOTS].push(Timer {
+            id,
+            deadline,
+            period: period.map(|period| period.max(1)),
+            action,
+        });
+        Some(id)
+    }
+
+    //Swap the waker of an armed timer, e.g. when a future moved to another task.
+    pub fn update_waker(&mut self, id: TimerId, waker: &Waker) -> bool {
+        for timer in self.slots.iter_mut().flatten() {
+            if timer.id == id {
+                if let Action::Wake(old) = &mut timer.action {
+                    if !old.will_wake(waker) {
+                        *old = waker.clone();
+                    }
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    //The removed timer is handed back so the caller can drop it outside of
+    //any lock (dropping a Waker may free memory).
+    pub fn remove(&mut self, id: TimerId) -> Option<Action> {
+        for slot in self.slots.iter_mut() {
+            if let Some(index) = slot.iter().position(|timer| timer.id == id) {
+                self.count -= 1;
+                return Some(slot.swap_remove(index).action);
+            }
+        }
+        None
+    }
+
+    //Fire everything that expired up to and including `now`.
+    pub fn advance(&mut self, now: u64) {
+        while self.last_processed < now {
+            self.last_processed += 1;
+            let tick = self.last_processed;
+            let slot = tick as usize % WHEEL_SLOTS;
+
+            let mut index = 0;
+            while index < self.slots[slot].len() {
+                if self.slots[slot][index].deadline > tick {
+                    index += 1;
+                    continue;
+                }
+                let mut timer = self.slots[slot].swap_remove(index);
+                match timer.period {
+                    Some(period) => {
+                        fire(&timer.action);
+                        timer.deadline += period;
+                        // fits, every slot has room for all timers. If it lands
+                        // in this slot again it is skipped below, as its new
+                        // deadline is in the future.
+                        self.slots[timer.deadline as usize % WHEEL_SLOTS].push(timer);
+                    }
+                    None => {
+                        self.count -= 1;
+                        match timer.action {
+                            Action::Callback(callback) => callback(),
+                            Action::Wake(waker) => waker.wake(),
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn fire(action: &Action) {
+    match action {
+        Action::Callback(callback) => callback(),
+        Action::Wake(waker) => waker.wake_by_ref(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::sync::Arc;
+    use alloc::task::Wake;
+    use spin::Mutex;
+
+    //Wakes by writing its deadline into a shared log.
+    struct Recorder {
+        deadline: u64,
+        log: Arc<Mutex<Vec<u64>>>,
+    }
+
+    impl Wake for Recorder {
+        fn wake(self: Arc<Self>) {
+            self.wake_by_ref();
+        }
+
+        fn wake_by_ref(self: &Arc<Self>) {
+            self.log.lock().push(self.deadline);
+        }
+    }
+
+    fn insert(
+        wheel: &mut TimerWheel,
+        log: &Arc<Mutex<Vec<u64>>>,
+        deadline: u64,
+        period: Option<u64>,
+    ) -> TimerId {
+        let recorder = Recorder {
+            deadline,
+            log: log.clone(),
+        };
+        let action = Action::Wake(Waker::from(Arc::new(recorder)));
+        wheel.insert(deadline, period, action).unwrap()
+    }
+
+    #[test_case]
+    fn timers_fire_in_order_across_revolutions() {
+        let mut wheel = TimerWheel::new(0);
+        let log = Arc::new(Mutex::new(Vec::new()));
+        //6 and 70 share a slot, 70 is one revolution later
+        for deadline in [70, 6, 3] {
+            insert(&mut wheel, &log, deadline, None);
+        }
+        wheel.advance(5);
+        assert_eq!(*log.lock(), [3]);
+        wheel.advance(69);
+        assert_eq!(*log.lock(), [3, 6]);
+        wheel.advance(70);
+        assert_eq!(*log.lock(), [3, 6, 70]);
+        assert_eq!(wheel.count, 0);
+    }
+
+    #[test_case]
+    fn past_deadlines_fire_on_the_next_tick() {
+        let mut wheel = TimerWheel::new(10);
+        let log = Arc::new(Mutex::new(Vec::new()));
+        insert(&mut wheel, &log, 4, None);
+        wheel.advance(10);
+        assert!(log.lock().is_empty());
+        wheel.advance(11);
+        assert_eq!(*log.lock(), [4]);
+    }
+
+    #[test_case]
+    fn cancelled_timers_do_not_fire() {
+        let mut wheel = TimerWheel::new(0);
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let cancelled = insert(&mut wheel, &log, 5, None);
+        insert(&mut wheel, &log, 6, None);
+        assert!(wheel.remove(cancelled).is_some());
+        assert!(wheel.remove(cancelled).is_none());
+        assert_eq!(wheel.count, 1);
+        wheel.advance(100);
+        assert_eq!(*log.lock(), [6]);
+    }
+
+    #[test_case]
+    fn periodic_timers_rearm() {
+        let mut wheel = TimerWheel::new(0);
+        let log = Arc::new(Mutex::new(Vec::new()));
+        //a period of a whole revolution lands in the slot being processed
+        insert(&mut wheel, &log, 2, Some(WHEEL_SLOTS as u64));
+        insert(&mut wheel, &log, 5, Some(10));
+        wheel.advance(2 + 2 * WHEEL_SLOTS as u64);
+        let log = log.lock();
+        assert_eq!(log.iter().filter(|&&deadline| deadline == 2).count(), 3);
+        assert_eq!(log.iter().filter(|&&deadline| deadline == 5).count(), 13);
+        assert_eq!(wheel.count, 2);
+    }
+
+    #[test_case]
+    fn the_wheel_holds_max_timers() {
+        let mut wheel = TimerWheel::new(0);
+        for deadline in 0..MAX_TIMERS as u64 {
+            assert!(wheel
+                .insert(deadline, None, Action::Callback(|| {}))
+                .is_some());
+        }
+        assert!(wheel.insert(1, None, Action::Callback(|| {})).is_none());
+    }
+}
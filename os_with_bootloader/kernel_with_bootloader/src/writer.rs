@@ -1,180 +1,742 @@
+mod ansi;
 mod constants;
+mod cursor;
+mod dirty;
+mod font;
+mod grid;
+mod scrollback;
 
+use crate::graphics::{self, Canvas};
+use crate::time;
+use alloc::vec::Vec;
+use ansi::{Action, Csi, Parser};
 use bootloader_api::info::{FrameBufferInfo, PixelFormat};
-use constants::font_constants;
-use constants::font_constants::{BACKUP_CHAR, CHAR_RASTER_HEIGHT, FONT_WEIGHT};
-use core::{fmt, ptr};
-use noto_sans_mono_bitmap::{get_raster, RasterizedChar};
+use constants::font_constants::{CHAR_RASTER_HEIGHT, FONT_WEIGHT};
+use core::fmt;
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+pub use cursor::CursorShape;
+pub use dirty::{DirtyRegion, Rect};
+pub use font::{Font, PsfError, PsfFont};
+use futures_util::stream::StreamExt;
+use grid::{Cell, Grid, Style};
+use scrollback::Scrollback;
+use x86_64::instructions::interrupts;
 
 const LINE_SPACING: usize = 2;
 const LETTER_SPACING: usize = 0;
 const BORDER_PADDING: usize = 1;
+const SCROLLBACK_LINES: usize = 500;
+const CURSOR_BLINK_INTERVAL: Duration = Duration::from_millis(500);
+//Thickness in pixels of the underline and bar cursors.
+const CURSOR_THICKNESS: usize = 2;
 
-fn get_char_raster(c: char) -> RasterizedChar {
-    fn get(c: char) -> Option<RasterizedChar> {
-        get_raster(c, FONT_WEIGHT, CHAR_RASTER_HEIGHT)
-    }
-    get(c).unwrap_or_else(|| get(BACKUP_CHAR).expect("Should get raster of backup char."))
+//Size of a cell for `font` in pixels, as (width, height).
+fn cell_size(font: &Font) -> (usize, usize) {
+    let (width, height) = font.glyph_size();
+    (width + LETTER_SPACING, height + LINE_SPACING)
 }
 
 pub struct FrameBufferWriter {
     framebuffer: &'static mut [u8],
+    //Everything is drawn here and copied to the framebuffer by flush(),
+    //reading and writing video memory pixel by pixel is slow. Empty if
+    //there was no memory for it, then drawing goes straight to the screen.
+    back_buffer: Vec<u8>,
+    //What changed in the back buffer since the last flush.
+    dirty: DirtyRegion,
     info: FrameBufferInfo,
-    x_pos: usize,
-    y_pos: usize,
-    color: [u8; 4],
+    font: Font,
+    //Size of a cell in pixels, from the font.
+    char_width: usize,
+    line_height: usize,
+    //What is on screen, the framebuffer is drawn from it.
+    grid: Grid,
+    scrollback: Scrollback,
+    //Cursor in cells. `column` can be one past the last column, the next
+    //character then wraps.
+    row: usize,
+    column: usize,
+    style: Style,
+    //Written characters push the rest of the row right instead of
+    //replacing it.
+    insert_mode: bool,
+    //Escape sequences in the output, see writer/ansi.rs.
+    parser: Parser,
+    //Cursor and style kept by ESC 7 or ESC [ s.
+    saved_cursor: (usize, usize, Style),
+    cursor_shape: CursorShape,
+    cursor_enabled: bool,
+    //The cell the cursor is drawn over, None while it is off.
+    cursor_drawn: Option<(usize, usize)>,
+    //Tick of the last change, the cursor is off during output.
+    last_output: u64,
+    //Set while the writer is in use, blink() then leaves the cursor alone.
+    busy: AtomicBool,
 }
 
 impl FrameBufferWriter {
+    //[r, g, b, a], the pale yellow the writer always used
+    pub const DEFAULT_COLOR: [u8; 4] = [255, 255, 128, 255];
+    pub const DEFAULT_BACKGROUND: [u8; 4] = [0, 0, 0, 255];
+    const DEFAULT_STYLE: Style = Style {
+        foreground: Self::DEFAULT_COLOR,
+        background: Self::DEFAULT_BACKGROUND,
+        bold: false,
+        reverse: false,
+    };
+
+    //Needs the heap for the grid, the scrollback and the back buffer.
     pub fn new(framebuffer: &'static mut [u8], info: FrameBufferInfo) -> Self {
+        let font = Font::Noto {
+            weight: FONT_WEIGHT,
+            size: CHAR_RASTER_HEIGHT,
+        };
+        let (char_width, line_height) = cell_size(&font);
+        let rows = (info.height - 2 * BORDER_PADDING) / line_height;
+        let columns = (info.width - 2 * BORDER_PADDING) / char_width;
+        let style = Self::DEFAULT_STYLE;
+        let mut back_buffer = Vec::new();
+        match back_buffer.try_reserve_exact(framebuffer.len()) {
+            Ok(()) => back_buffer.resize(framebuffer.len(), 0),
+            Err(_) => log::warn!(
+                "no memory for a {} KiB back buffer, drawing directly",
+                framebuffer.len() / 1024
+            ),
+        }
         let mut logger = Self {
             framebuffer,
+            back_buffer,
+            dirty: DirtyRegion::new(),
             info,
-            x_pos: 0,
-            y_pos: 0,
-            color: [255, 255, 255, 255],
+            font,
+            char_width,
+            line_height,
+            grid: Grid::new(rows, columns, Cell::blank(style)),
+            scrollback: Scrollback::new(columns, SCROLLBACK_LINES),
+            row: 0,
+            column: 0,
+            style,
+            insert_mode: false,
+            parser: Parser::new(),
+            saved_cursor: (0, 0, style),
+            cursor_shape: CursorShape::Block,
+            cursor_enabled: true,
+            cursor_drawn: None,
+            last_output: 0,
+            busy: AtomicBool::new(false),
         };
         logger.clear();
         logger
     }
 
     //? CA Question A (1)
-    pub fn set_pos(&mut self, height: usize, width: usize) {
-        self.x_pos += width;
-        self.y_pos += height;
+    //Move the cursor to a cell, clamped to the screen.
+    pub fn set_pos(&mut self, row: usize, column: usize) {
+        self.update(|writer| writer.move_to(row, column));
+    }
+
+    fn move_to(&mut self, row: usize, column: usize) {
+        self.row = row.min(self.grid.rows() - 1);
+        self.column = column.min(self.grid.columns() - 1);
+    }
+
+    //Draw with `font` from now on, everything on screen included. Returns
+    //false if a single character does not fit on the screen.
+    pub fn set_font(&mut self, font: Font) -> bool {
+        let (char_width, line_height) = cell_size(&font);
+        if char_width + 2 * BORDER_PADDING > self.info.width
+            || line_height + 2 * BORDER_PADDING > self.info.height
+        {
+            return false;
+        }
+        self.update(|writer| {
+            writer.font = font;
+            writer.char_width = char_width;
+            writer.line_height = line_height;
+            writer.relayout();
+        });
+        true
+    }
+
+    pub fn font(&self) -> &Font {
+        &self.font
+    }
+
+    //Size of the screen in cells, as (rows, columns).
+    pub fn size(&self) -> (usize, usize) {
+        (self.grid.rows(), self.grid.columns())
     }
 
+    //Make the grid fit the cell size, keeping the text around the cursor.
+    //Rows above it that no longer fit go to the scrollback.
+    fn relayout(&mut self) {
+        let rows = (self.info.height - 2 * BORDER_PADDING) / self.line_height;
+        let columns = (self.info.width - 2 * BORDER_PADDING) / self.char_width;
+        let mut grid = Grid::new(rows, columns, self.blank());
+        let first = (self.row + 1).saturating_sub(rows);
+        self.scrollback.set_columns(columns);
+        for row in 0..first {
+            self.scrollback.push(self.grid.row(row));
+        }
+        for row in first..self.grid.rows().min(first + rows) {
+            for (column, cell) in self.grid.row(row).iter().take(columns).enumerate() {
+                grid.set(row - first, column, *cell);
+            }
+        }
+        self.grid = grid;
+        self.row -= first;
+        self.column = self.column.min(columns);
+        self.scrollback.reset_view();
+        self.buffer().fill(0);
+        self.dirty
+            .add(Rect::new(0, 0, self.info.width, self.info.height));
+        self.redraw();
+    }
+
+    //Cursor position as (row, column).
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.row, self.column)
+    }
+
+    //Text color for what is written next, [r, g, b, a]. The alpha byte is
+    //not used, glyphs are blended into the background by their intensity.
     pub fn set_color(&mut self, color: [u8; 4]) {
-        self.color = color;
+        self.style.foreground = color;
+    }
+
+    pub fn color(&self) -> [u8; 4] {
+        self.style.foreground
+    }
+
+    //Characters written in insert mode push the rest of the row right.
+    pub fn set_insert_mode(&mut self, insert_mode: bool) {
+        self.insert_mode = insert_mode;
+    }
+
+    //What is on the live screen at a cell.
+    #[cfg(test)]
+    pub fn cell(&self, row: usize, column: usize) -> Option<Cell> {
+        self.grid.get(row, column)
+    }
+
+    fn blank(&self) -> Cell {
+        Cell::blank(self.style)
     }
 
     fn newline(&mut self) {
-        self.y_pos += font_constants::CHAR_RASTER_HEIGHT.val() + LINE_SPACING;
+        if self.row + 1 < self.grid.rows() {
+            self.row += 1;
+        } else {
+            self.scroll_up();
+        }
         self.carriage_return()
     }
 
     fn carriage_return(&mut self) {
-        self.x_pos = BORDER_PADDING;
+        self.column = 0;
     }
 
-    fn width(&self) -> usize {
-        self.info.width
+    pub fn clear(&mut self) {
+        self.update(Self::clear_screen);
     }
 
-    fn height(&self) -> usize {
-        self.info.height
+    fn clear_screen(&mut self) {
+        self.row = 0;
+        self.column = 0;
+        self.grid.clear(self.blank());
+        self.scrollback.reset_view();
+        self.buffer().fill(0);
+        self.dirty
+            .add(Rect::new(0, 0, self.info.width, self.info.height));
+        self.redraw();
     }
 
-    pub fn clear(&mut self) {
-        self.x_pos = BORDER_PADDING;
-        self.y_pos = BORDER_PADDING;
-        self.framebuffer.fill(0);
+    //Everything that draws or moves the cursor goes through here: the
+    //cursor is taken off first, and with interrupts off no other kernel
+    //thread can get at the writer halfway through.
+    fn update<T>(&mut self, change: impl FnOnce(&mut Self) -> T) -> T {
+        interrupts::without_interrupts(|| {
+            let nested = self.busy.swap(true, Ordering::Acquire);
+            self.hide_cursor();
+            let result = change(self);
+            self.last_output = time::ticks();
+            self.flush();
+            if !nested {
+                self.busy.store(false, Ordering::Release);
+            }
+            result
+        })
+    }
+
+    //Turn the cursor on or off as the blinking wants. Does nothing while the
+    //writer is in use.
+    pub fn blink(&mut self, now: u64) {
+        if self.busy.swap(true, Ordering::Acquire) {
+            return;
+        }
+        interrupts::without_interrupts(|| {
+            let interval = time::duration_to_ticks(CURSOR_BLINK_INTERVAL);
+            let on = cursor::blink_on(now, self.last_output, interval);
+            match (on, self.cursor_drawn) {
+                (true, None) => self.show_cursor(),
+                (false, Some(_)) => self.hide_cursor(),
+                _ => {}
+            }
+            self.flush();
+        });
+        self.busy.store(false, Ordering::Release);
+    }
+
+    fn show_cursor(&mut self) {
+        //not over the scrollback, it belongs to the live screen
+        if !self.cursor_enabled || self.scrollback.offset() > 0 {
+            return;
+        }
+        let (row, column) = (self.row, self.column.min(self.grid.columns() - 1));
+        let mut cell = match self.grid.get(row, column) {
+            Some(cell) => cell,
+            None => return,
+        };
+        let (width, height) = (self.char_width, self.line_height);
+        let (x, y) = (column * width, row * height);
+        let (foreground, _) = cell.style.colors();
+        match self.cursor_shape {
+            CursorShape::Block => {
+                cell.style.reverse = !cell.style.reverse;
+                self.draw_cell(row, column, cell);
+            }
+            CursorShape::Underline => self.canvas_unhidden().fill_rect(
+                (BORDER_PADDING + x) as i32,
+                (BORDER_PADDING + y + height - CURSOR_THICKNESS) as i32,
+                width as i32,
+                CURSOR_THICKNESS as i32,
+                foreground,
+            ),
+            CursorShape::Bar => self.canvas_unhidden().fill_rect(
+                (BORDER_PADDING + x) as i32,
+                (BORDER_PADDING + y) as i32,
+                CURSOR_THICKNESS as i32,
+                height as i32,
+                foreground,
+            ),
+        }
+        self.cursor_drawn = Some((row, column));
+    }
+
+    //Draw the cell under the cursor without it.
+    fn hide_cursor(&mut self) {
+        if let Some((row, column)) = self.cursor_drawn.take() {
+            if let Some(cell) = self.grid.get(row, column) {
+                self.draw_cell(row, column, cell);
+            }
+        }
+    }
+
+    //Where drawing goes.
+    fn buffer(&mut self) -> &mut [u8] {
+        match self.back_buffer.is_empty() {
+            true => self.framebuffer,
+            false => &mut self.back_buffer,
+        }
+    }
+
+    //Draw shapes and bitmaps where the text goes, text written over it
+    //replaces it. Goes through update(), so the cursor can't blink into the
+    //drawing while the canvas is in use.
+    pub fn draw<T>(&mut self, drawing: impl FnOnce(&mut Canvas) -> T) -> T {
+        self.update(|writer| drawing(&mut writer.canvas_unhidden()))
+    }
+
+    fn canvas_unhidden(&mut self) -> Canvas<'_> {
+        let buffer = match self.back_buffer.is_empty() {
+            true => &mut *self.framebuffer,
+            false => &mut self.back_buffer[..],
+        };
+        Canvas::new(buffer, self.info).track(&mut self.dirty)
+    }
+
+    //Copy what changed to the screen.
+    pub fn flush(&mut self) {
+        if !self.back_buffer.is_empty() {
+            let bytes_per_pixel = self.info.bytes_per_pixel;
+            let row_bytes = self.info.stride * bytes_per_pixel;
+            for rect in self.dirty.rects() {
+                for y in rect.y..rect.bottom() {
+                    let start = y * row_bytes + rect.x * bytes_per_pixel;
+                    let end = start + rect.width * bytes_per_pixel;
+                    self.framebuffer[start..end].copy_from_slice(&self.back_buffer[start..end]);
+                }
+            }
+        }
+        self.dirty.clear();
+    }
+
+    //Move everything up by one row, the top row goes to the scrollback.
+    fn scroll_up(&mut self) {
+        self.scrollback.push(self.grid.row(0));
+        self.grid.scroll_up(self.blank());
+
+        let row_bytes = self.info.stride * self.info.bytes_per_pixel;
+        let text_start = BORDER_PADDING * row_bytes;
+        let line_bytes = self.line_height * row_bytes;
+        let text_end = text_start + self.grid.rows() * line_bytes;
+        self.buffer()
+            .copy_within(text_start + line_bytes..text_end, text_start);
+        self.dirty.add(Rect::new(
+            0,
+            BORDER_PADDING,
+            self.info.width,
+            self.grid.rows() * self.line_height,
+        ));
+        self.redraw_row(self.grid.rows() - 1, 0..usize::MAX);
+    }
+
+    //Browse the scrollback, by half a screen per call.
+    pub fn scroll_back(&mut self) {
+        self.update(|writer| writer.scroll_view(1));
+    }
+
+    pub fn scroll_forward(&mut self) {
+        self.update(|writer| writer.scroll_view(-1));
+    }
+
+    fn scroll_view(&mut self, direction: isize) {
+        let lines = (self.grid.rows() / 2).max(1) as isize;
+        if self.scrollback.scroll_view(direction * lines) {
+            self.redraw();
+        }
+    }
+
+    //Back to the live screen; new output always shows up there.
+    fn leave_scrollback(&mut self) {
+        if self.scrollback.offset() > 0 {
+            self.scrollback.reset_view();
+            self.redraw();
+        }
+    }
+
+    //Draw the whole screen from the current view.
+    fn redraw(&mut self) {
+        for row in 0..self.grid.rows() {
+            for column in 0..self.grid.columns() {
+                let cell = self
+                    .scrollback
+                    .view_line(row, &self.grid)
+                    .get(column)
+                    .copied()
+                    .unwrap_or(Cell::blank(Self::DEFAULT_STYLE));
+                self.draw_cell(row, column, cell);
+            }
+        }
+    }
+
+    //Draw some cells of a live row.
+    fn redraw_row(&mut self, row: usize, columns: Range<usize>) {
+        for column in columns.start..columns.end.min(self.grid.columns()) {
+            let cell = self.grid.row(row)[column];
+            self.draw_cell(row, column, cell);
+        }
     }
 
     fn write_char(&mut self, c: char) {
+        self.leave_scrollback();
+        match self.parser.advance(c) {
+            Some(Action::Print(c)) => self.print(c),
+            Some(Action::Escape('7')) => self.save_cursor(),
+            Some(Action::Escape('8')) => self.restore_cursor(),
+            Some(Action::Csi(csi)) => self.control(csi),
+            _ => {}
+        }
+    }
+
+    fn print(&mut self, c: char) {
         match c {
             '\n' => self.newline(),
             '\r' => self.carriage_return(),
+            '\t' => self.tab_stop(),
+            '\u{0008}' => self.cursor_left(),
+            c if c.is_control() => {}
             c => {
-                let new_xpos = self.x_pos + font_constants::CHAR_RASTER_WIDTH;
-                if new_xpos >= self.width() {
+                if self.column >= self.grid.columns() {
                     self.newline();
                 }
-                let new_ypos =
-                    self.y_pos + font_constants::CHAR_RASTER_HEIGHT.val() + BORDER_PADDING;
-                if new_ypos >= self.height() {
-                    self.clear();
+                let cell = Cell {
+                    character: c,
+                    style: self.style,
+                };
+                if self.insert_mode {
+                    self.grid.insert(self.row, self.column, cell);
+                    self.redraw_row(self.row, self.column..usize::MAX);
+                } else {
+                    self.grid.set(self.row, self.column, cell);
+                    self.draw_cell(self.row, self.column, cell);
                 }
-                self.write_rendered_char(get_char_raster(c));
+                self.column += 1;
             }
         }
     }
 
     //? CA Question B (i)
     pub fn backspace(&mut self) {
-        if self.x_pos > BORDER_PADDING {
-            self.x_pos -= font_constants::CHAR_RASTER_WIDTH;
-        } else {
-            if self.y_pos
-                >= font_constants::CHAR_RASTER_HEIGHT.val() + LINE_SPACING + BORDER_PADDING
-            {
-                self.x_pos = self.width() - (font_constants::CHAR_RASTER_WIDTH + LETTER_SPACING);
-                self.y_pos -= font_constants::CHAR_RASTER_HEIGHT.val() + LINE_SPACING;
-            } else {
-                return;
+        self.update(|writer| {
+            writer.leave_scrollback();
+            if writer.row > 0 || writer.column > 0 {
+                writer.cursor_left();
+                let blank = writer.blank();
+                writer.grid.set(writer.row, writer.column, blank);
+                writer.draw_cell(writer.row, writer.column, blank);
             }
-        }
+        });
+    }
 
-        for u in 0..font_constants::CHAR_RASTER_HEIGHT.val() {
-            for w in 0..font_constants::CHAR_RASTER_WIDTH {
-                self.write_pixel(self.x_pos + w, self.y_pos + u, 0);
+    //Remove the character under the cursor, the rest of the row moves left.
+    pub fn delete_char(&mut self) {
+        self.update(|writer| {
+            writer.leave_scrollback();
+            if writer.column < writer.grid.columns() {
+                writer
+                    .grid
+                    .delete(writer.row, writer.column, writer.blank());
+                writer.redraw_row(writer.row, writer.column..usize::MAX);
             }
-        }
+        });
     }
 
     //? Extras
     pub fn arrow_up(&mut self) {
-        if self.y_pos > BORDER_PADDING {
-            self.y_pos -= font_constants::CHAR_RASTER_HEIGHT.val() + LINE_SPACING;
-        }
+        self.update(|writer| writer.row = writer.row.saturating_sub(1));
     }
 
     pub fn arrow_down(&mut self) {
-        if self.y_pos + font_constants::CHAR_RASTER_HEIGHT.val() + LINE_SPACING < self.height() {
-            self.y_pos += font_constants::CHAR_RASTER_HEIGHT.val() + LINE_SPACING;
-        }
+        self.update(|writer| {
+            if writer.row + 1 < writer.grid.rows() {
+                writer.row += 1;
+            }
+        });
     }
 
     pub fn arrow_left(&mut self) {
-        if self.x_pos > BORDER_PADDING {
-            self.x_pos -= font_constants::CHAR_RASTER_WIDTH;
-        }
+        self.update(Self::cursor_left);
     }
 
     pub fn arrow_right(&mut self) {
-        if self.x_pos + font_constants::CHAR_RASTER_WIDTH < self.width() {
-            self.x_pos += font_constants::CHAR_RASTER_WIDTH;
-        }
+        self.update(Self::cursor_right);
     }
 
     pub fn tab(&mut self) {
-        const TAB_WIDTH: usize = 4; // Number of characters to jump on tab
+        self.update(Self::tab_stop);
+    }
+
+    //Left and right wrap between rows, so they undo and redo what was
+    //written.
+    fn cursor_left(&mut self) {
+        if self.column > 0 {
+            self.column = self.column.min(self.grid.columns()) - 1;
+        } else if self.row > 0 {
+            self.row -= 1;
+            self.column = self.grid.columns() - 1;
+        }
+    }
+
+    fn cursor_right(&mut self) {
+        if self.column + 1 < self.grid.columns() || self.row + 1 == self.grid.rows() {
+            self.column = (self.column + 1).min(self.grid.columns());
+        } else {
+            self.row += 1;
+            self.column = 0;
+        }
+    }
 
-        let remaining_space = self.width() - self.x_pos;
-        let tab_width = font_constants::CHAR_RASTER_WIDTH * TAB_WIDTH;
+    fn tab_stop(&mut self) {
+        const TAB_WIDTH: usize = 4; // Number of characters to jump on tab
 
-        if remaining_space >= tab_width {
-            self.x_pos += tab_width;
+        if self.column + TAB_WIDTH <= self.grid.columns() {
+            self.column += TAB_WIDTH;
         } else {
             self.newline();
         }
     }
 
-    fn write_rendered_char(&mut self, rendered_char: RasterizedChar) {
-        for (y, row) in rendered_char.raster().iter().enumerate() {
-            for (x, byte) in row.iter().enumerate() {
-                self.write_pixel(self.x_pos + x, self.y_pos + y, *byte);
+    //ESC 7 and ESC [ s
+    fn save_cursor(&mut self) {
+        self.saved_cursor = (self.row, self.column, self.style);
+    }
+
+    //ESC 8 and ESC [ u
+    fn restore_cursor(&mut self) {
+        let (row, column, style) = self.saved_cursor;
+        self.move_to(row, column);
+        self.style = style;
+    }
+
+    fn control(&mut self, csi: Csi) {
+        if csi.private {
+            //DECTCEM shows and hides the cursor, other private modes are
+            //not supported
+            match (csi.params(), csi.final_char) {
+                ([25], 'h') => self.cursor_enabled = true,
+                ([25], 'l') => self.cursor_enabled = false,
+                _ => {}
+            }
+            return;
+        }
+        let count = csi.param(0, 1) as usize;
+        let last_row = self.grid.rows() - 1;
+        let last_column = self.grid.columns() - 1;
+        match csi.final_char {
+            //CUU, CUD, CUF, CUB
+            'A' => self.row = self.row.saturating_sub(count),
+            'B' => self.row = (self.row + count).min(last_row),
+            'C' => self.column = (self.column + count).min(last_column),
+            'D' => self.column = self.column.min(last_column).saturating_sub(count),
+            //CUP, rows and columns count from 1
+            'H' | 'f' => self.move_to(csi.param(0, 1) as usize - 1, csi.param(1, 1) as usize - 1),
+            //EL and ED
+            'K' => self.erase_line(csi.param(0, 0)),
+            'J' => self.erase_display(csi.param(0, 0)),
+            'm' => self.select_graphic_rendition(csi.params()),
+            's' => self.save_cursor(),
+            'u' => self.restore_cursor(),
+            //DECSCUSR
+            'q' if csi.intermediate == ' ' => {
+                if let Some(shape) = CursorShape::from_decscusr(csi.param(0, 0)) {
+                    self.cursor_shape = shape;
+                }
             }
+            _ => {}
+        }
+    }
+
+    //0 erases from the cursor to the end of the line, 1 from the start to
+    //the cursor, 2 the whole line.
+    fn erase_line(&mut self, mode: u16) {
+        let columns = match mode {
+            0 => self.column..usize::MAX,
+            1 => 0..self.column + 1,
+            _ => 0..usize::MAX,
+        };
+        self.grid
+            .clear_columns(self.row, columns.clone(), self.blank());
+        self.redraw_row(self.row, columns);
+    }
+
+    //Like erase_line, for the screen. The cursor stays where it is.
+    fn erase_display(&mut self, mode: u16) {
+        let rows = match mode {
+            0 => self.row + 1..self.grid.rows(),
+            1 => 0..self.row,
+            _ => 0..self.grid.rows(),
+        };
+        for row in rows {
+            self.grid.clear_columns(row, 0..usize::MAX, self.blank());
+            self.redraw_row(row, 0..usize::MAX);
+        }
+        match mode {
+            0 | 1 => self.erase_line(mode),
+            _ => {}
         }
-        self.x_pos += rendered_char.width() + LETTER_SPACING;
     }
 
-    fn write_pixel(&mut self, x: usize, y: usize, intensity: u8) {
-        let pixel_offset = y * self.info.stride + x;
-        let color = match self.info.pixel_format {
-            PixelFormat::Rgb => [intensity, intensity, intensity / 2, 0],
-            PixelFormat::Bgr => [intensity / 2, intensity, intensity, 0],
-            PixelFormat::U8 => [if intensity > 200 { 0xf } else { 0 }, 0, 0, 0],
-            other => {
-                self.info.pixel_format = PixelFormat::Rgb;
-                panic!("pixel format {:?} not supported in logger", other)
+    //SGR, ESC [ ... m
+    fn select_graphic_rendition(&mut self, params: &[u16]) {
+        if params.is_empty() {
+            self.style = Self::DEFAULT_STYLE;
+        }
+        let mut params = params.iter().copied();
+        while let Some(param) = params.next() {
+            match param {
+                0 => self.style = Self::DEFAULT_STYLE,
+                1 => self.style.bold = true,
+                22 => self.style.bold = false,
+                7 => self.style.reverse = true,
+                27 => self.style.reverse = false,
+                30..=37 => self.style.foreground = ansi::palette(param as u8 - 30),
+                90..=97 => self.style.foreground = ansi::palette(param as u8 - 90 + 8),
+                39 => self.style.foreground = Self::DEFAULT_COLOR,
+                40..=47 => self.style.background = ansi::palette(param as u8 - 40),
+                100..=107 => self.style.background = ansi::palette(param as u8 - 100 + 8),
+                49 => self.style.background = Self::DEFAULT_BACKGROUND,
+                38 | 48 => {
+                    let color = match (params.next(), params.next()) {
+                        (Some(5), Some(index)) => ansi::palette(index as u8),
+                        (Some(2), Some(r)) => {
+                            let g = params.next().unwrap_or(0);
+                            let b = params.next().unwrap_or(0);
+                            [r as u8, g as u8, b as u8, 255]
+                        }
+                        _ => return,
+                    };
+                    match param {
+                        38 => self.style.foreground = color,
+                        _ => self.style.background = color,
+                    }
+                }
+                _ => {}
             }
+        }
+    }
+
+    //Paint the whole box of a cell, so whatever was there before is gone.
+    fn draw_cell(&mut self, row: usize, column: usize, cell: Cell) {
+        if graphics::pixel_bytes(self.info.pixel_format, [0; 4]).is_none() {
+            let format = self.info.pixel_format;
+            self.info.pixel_format = PixelFormat::Rgb;
+            panic!("pixel format {:?} not supported in logger", format)
+        }
+        let (foreground, background) = cell.style.colors();
+        let x_pos = BORDER_PADDING + column * self.char_width;
+        let y_pos = BORDER_PADDING + row * self.line_height;
+        self.dirty
+            .add(Rect::new(x_pos, y_pos, self.char_width, self.line_height));
+        let glyph = self.font.glyph(cell.character, cell.style.bold);
+        let buffer = match self.back_buffer.is_empty() {
+            true => &mut *self.framebuffer,
+            false => &mut self.back_buffer[..],
         };
-        let bytes_per_pixel = self.info.bytes_per_pixel;
-        let byte_offset = pixel_offset * bytes_per_pixel;
-        self.framebuffer[byte_offset..(byte_offset + bytes_per_pixel)]
-            .copy_from_slice(&color[..bytes_per_pixel]);
-        let _ = unsafe { ptr::read_volatile(&self.framebuffer[byte_offset]) };
+        for y in 0..self.line_height {
+            for x in 0..self.char_width {
+                let intensity = glyph.intensity(x, y);
+                let (x, y) = (x_pos + x, y_pos + y);
+                write_pixel(buffer, &self.info, x, y, intensity, foreground, background);
+            }
+        }
+    }
+}
+
+//`intensity` is how much of the glyph covers the pixel, the rest shows the
+//background. The pixel format has to be supported.
+fn write_pixel(
+    buffer: &mut [u8],
+    info: &FrameBufferInfo,
+    x: usize,
+    y: usize,
+    intensity: u8,
+    foreground: [u8; 4],
+    background: [u8; 4],
+) {
+    let pixel_offset = y * info.stride + x;
+    let blend = |channel: usize| {
+        let fg = foreground[channel] as u16 * intensity as u16;
+        let bg = background[channel] as u16 * (255 - intensity) as u16;
+        ((fg + bg) / 255) as u8
+    };
+    let blended = [blend(0), blend(1), blend(2), 0];
+    let color = graphics::pixel_bytes(info.pixel_format, blended).unwrap_or_default();
+    let bytes_per_pixel = info.bytes_per_pixel;
+    let byte_offset = pixel_offset * bytes_per_pixel;
+    buffer[byte_offset..(byte_offset + bytes_per_pixel)].copy_from_slice(&color[..bytes_per_pixel]);
+}
+
+//Blinks the cursor, from a task so that the writer is never used inside an
+//interrupt handler.
+pub async fn blink_cursor() {
+    let mut ticks = time::TickStream::new();
+    while let Some(now) = ticks.next().await {
+        if let Some(mut writer) = unsafe { crate::FRAME_BUFFER_WRITER } {
+            unsafe { writer.as_mut() }.blink(now);
+        }
     }
 }
 
@@ -183,9 +745,381 @@ unsafe impl Sync for FrameBufferWriter {}
 
 impl fmt::Write for FrameBufferWriter {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        for c in s.chars() {
-            self.write_char(c);
-        }
+        self.update(|writer| s.chars().for_each(|c| writer.write_char(c)));
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+    use alloc::{format, vec};
+    use core::fmt::Write;
+
+    const WIDTH: usize = 160;
+    const HEIGHT: usize = 64;
+
+    //A writer drawing into a heap buffer instead of the real framebuffer.
+    fn test_writer() -> FrameBufferWriter {
+        test_writer_with(PixelFormat::Bgr, 4)
+    }
+
+    fn test_writer_with(pixel_format: PixelFormat, bytes_per_pixel: usize) -> FrameBufferWriter {
+        let info = FrameBufferInfo {
+            byte_len: WIDTH * HEIGHT * bytes_per_pixel,
+            width: WIDTH,
+            height: HEIGHT,
+            pixel_format,
+            bytes_per_pixel,
+            stride: WIDTH,
+        };
+        let buffer = vec![0xffu8; info.byte_len].leak();
+        FrameBufferWriter::new(buffer, info)
+    }
+
+    //The characters of a row, trailing blanks removed.
+    fn row_text(cells: &[Cell]) -> String {
+        let text: String = cells.iter().map(|cell| cell.character).collect();
+        String::from(text.trim_end())
+    }
+
+    #[test_case]
+    fn new_clears_the_framebuffer() {
+        let writer = test_writer();
+        assert!(writer.framebuffer.iter().all(|byte| *byte == 0));
+        assert_eq!(writer.cursor(), (0, 0));
+    }
+
+    #[test_case]
+    fn write_draws_and_stores_cells() {
+        let mut writer = test_writer();
+        write!(writer, "ab").unwrap();
+        assert!(writer.framebuffer.iter().any(|byte| *byte != 0));
+        assert_eq!(row_text(writer.grid.row(0)), "ab");
+        assert_eq!(writer.cell(0, 0).unwrap().style.foreground, writer.color());
+        assert_eq!(writer.cursor(), (0, 2));
+    }
+
+    #[test_case]
+    fn newline_moves_to_next_row() {
+        let mut writer = test_writer();
+        write!(writer, "a\nb").unwrap();
+        assert_eq!(row_text(writer.grid.row(1)), "b");
+        assert_eq!(writer.cursor(), (1, 1));
+    }
+
+    #[test_case]
+    fn long_lines_wrap() {
+        let mut writer = test_writer();
+        let columns = writer.grid.columns();
+        for _ in 0..columns + 1 {
+            write!(writer, "x").unwrap();
+        }
+        assert_eq!(writer.cursor(), (1, 1));
+        assert_eq!(writer.cell(1, 0).unwrap().character, 'x');
+    }
+
+    #[test_case]
+    fn long_output_stays_inside_the_buffer() {
+        let mut writer = test_writer();
+        for _ in 0..50 {
+            writeln!(writer, "a line that is wider than the test framebuffer").unwrap();
+        }
+        assert!(writer.row < writer.grid.rows());
+    }
+
+    #[test_case]
+    fn scrolling_moves_the_text_up() {
+        let mut writer = test_writer();
+        let line_bytes = writer.line_height * WIDTH * 4;
+        let first_line = BORDER_PADDING * WIDTH * 4;
+        let second_line = first_line + line_bytes;
+
+        write!(writer, "a\nb").unwrap();
+        let b = writer.framebuffer[second_line..second_line + line_bytes].to_vec();
+        writeln!(writer).unwrap();
+        writeln!(writer).unwrap();
+        assert_eq!(row_text(writer.grid.row(0)), "b");
+        assert_eq!(
+            &writer.framebuffer[first_line..first_line + line_bytes],
+            &b[..]
+        );
+    }
+
+    #[test_case]
+    fn scrolled_off_lines_are_kept() {
+        let mut writer = test_writer();
+        for i in 0..10 {
+            writeln!(writer, "line {}", i).unwrap();
+        }
+        let rows = writer.grid.rows();
+        assert!(writer.scrollback.scroll_view(isize::MAX));
+        assert_eq!(
+            row_text(writer.scrollback.view_line(0, &writer.grid)),
+            "line 0"
+        );
+        writer.scrollback.reset_view();
+        assert_eq!(
+            row_text(writer.scrollback.view_line(0, &writer.grid)),
+            format!("line {}", 11 - rows)
+        );
+    }
+
+    #[test_case]
+    fn set_pos_is_in_cells() {
+        let mut writer = test_writer();
+        writer.set_pos(1, 3);
+        write!(writer, "a").unwrap();
+        assert_eq!(writer.cell(1, 3).unwrap().character, 'a');
+        writer.set_pos(usize::MAX, usize::MAX);
+        assert_eq!(
+            writer.cursor(),
+            (writer.grid.rows() - 1, writer.grid.columns() - 1)
+        );
+    }
+
+    #[test_case]
+    fn backspace_erases_across_rows() {
+        let mut writer = test_writer();
+        let columns = writer.grid.columns();
+        for _ in 0..columns + 1 {
+            write!(writer, "x").unwrap();
+        }
+        writer.backspace();
+        writer.backspace();
+        assert_eq!(writer.cursor(), (0, columns - 1));
+        assert_eq!(writer.cell(0, columns - 1).unwrap().character, ' ');
+        assert_eq!(writer.cell(1, 0).unwrap().character, ' ');
+    }
+
+    #[test_case]
+    fn insert_mode_and_delete_shift_the_row() {
+        let mut writer = test_writer();
+        write!(writer, "ac").unwrap();
+        writer.arrow_left();
+        writer.set_insert_mode(true);
+        write!(writer, "b").unwrap();
+        assert_eq!(row_text(writer.grid.row(0)), "abc");
+        writer.arrow_left();
+        writer.arrow_left();
+        writer.delete_char();
+        assert_eq!(row_text(writer.grid.row(0)), "bc");
+    }
+
+    #[test_case]
+    fn escape_sequences_move_the_cursor() {
+        let mut writer = test_writer();
+        write!(writer, "\x1b[2;5Ha").unwrap();
+        assert_eq!(writer.cell(1, 4).unwrap().character, 'a');
+        write!(writer, "\x1b[A\x1b[2D\x1b7\x1b[3B\x1b[9C").unwrap();
+        assert_eq!(writer.cursor(), (2, 12));
+        write!(writer, "\x1b8").unwrap();
+        assert_eq!(writer.cursor(), (0, 3));
+    }
+
+    #[test_case]
+    fn escape_sequences_set_the_style() {
+        let mut writer = test_writer();
+        write!(writer, "\x1b[1;31;48;5;16ma\x1b[38;2;1;2;3;7mb\x1b[0mc").unwrap();
+        let a = writer.cell(0, 0).unwrap().style;
+        assert!(a.bold && !a.reverse);
+        assert_eq!(a.foreground, ansi::palette(1));
+        assert_eq!(a.background, [0, 0, 0, 255]);
+        let b = writer.cell(0, 1).unwrap().style;
+        assert_eq!(b.colors(), (a.background, [1, 2, 3, 255]));
+        assert_eq!(
+            writer.cell(0, 2).unwrap().style,
+            FrameBufferWriter::DEFAULT_STYLE
+        );
+    }
+
+    #[test_case]
+    fn escape_sequences_erase() {
+        let mut writer = test_writer();
+        write!(writer, "abcd\nefgh\nijkl\x1b[2;3H").unwrap();
+        write!(writer, "\x1b[K").unwrap();
+        assert_eq!(row_text(writer.grid.row(1)), "ef");
+        write!(writer, "\x1b[1J").unwrap();
+        assert_eq!(row_text(writer.grid.row(0)), "");
+        assert_eq!(row_text(writer.grid.row(2)), "ijkl");
+        write!(writer, "\x1b[2J").unwrap();
+        assert!((0..writer.grid.rows()).all(|row| row_text(writer.grid.row(row)).is_empty()));
+        assert_eq!(writer.cursor(), (1, 2));
+    }
+
+    //Bytes of the top left pixel of the first cell.
+    fn first_pixel(writer: &FrameBufferWriter) -> &[u8] {
+        let bytes_per_pixel = writer.info.bytes_per_pixel;
+        let offset = (BORDER_PADDING * WIDTH + BORDER_PADDING) * bytes_per_pixel;
+        &writer.framebuffer[offset..offset + bytes_per_pixel]
+    }
+
+    #[test_case]
+    fn background_is_drawn_in_the_pixel_format() {
+        let mut writer = test_writer_with(PixelFormat::Rgb, 3);
+        writer.style.background = [10, 20, 30, 255];
+        write!(writer, " ").unwrap();
+        assert_eq!(first_pixel(&writer), [10, 20, 30]);
+
+        let mut writer = test_writer();
+        writer.style.background = [10, 20, 30, 255];
+        write!(writer, " ").unwrap();
+        assert_eq!(first_pixel(&writer), [30, 20, 10, 0]);
+
+        let mut writer = test_writer_with(PixelFormat::U8, 1);
+        writer.style.background = [255, 255, 255, 255];
+        write!(writer, " ").unwrap();
+        assert_eq!(first_pixel(&writer), [255]);
+    }
+
+    #[test_case]
+    fn glyphs_blend_foreground_into_background() {
+        let mut writer = test_writer_with(PixelFormat::Rgb, 3);
+        let (foreground, background) = ([200, 0, 0, 0], [0, 0, 100, 255]);
+        for (x, intensity) in [255, 0, 128].into_iter().enumerate() {
+            let buffer = &mut writer.back_buffer;
+            write_pixel(
+                buffer,
+                &writer.info,
+                x,
+                0,
+                intensity,
+                foreground,
+                background,
+            );
+        }
+        assert_eq!(writer.back_buffer[..9], [200, 0, 0, 0, 0, 100, 100, 0, 49]);
+    }
+
+    #[test_case]
+    fn only_flush_reaches_the_screen() {
+        let mut writer = test_writer();
+        writer.print('a');
+        assert!(writer.back_buffer.iter().any(|byte| *byte != 0));
+        assert!(writer.framebuffer.iter().all(|byte| *byte == 0));
+        writer.flush();
+        assert_eq!(writer.framebuffer, &writer.back_buffer[..]);
+    }
+
+    //Pixels of a cell on screen.
+    fn cell_pixels(writer: &FrameBufferWriter, row: usize, column: usize) -> Vec<u8> {
+        let (char_width, line_height) = (writer.char_width, writer.line_height);
+        let x = BORDER_PADDING + column * char_width;
+        (0..line_height)
+            .flat_map(|y| {
+                let start = ((BORDER_PADDING + row * line_height + y) * WIDTH + x) * 4;
+                writer.framebuffer[start..start + char_width * 4]
+                    .iter()
+                    .copied()
+            })
+            .collect()
+    }
+
+    #[test_case]
+    fn cursor_shows_after_output_and_follows_moves() {
+        let mut writer = test_writer();
+        write!(writer, "a").unwrap();
+        let blank = cell_pixels(&writer, 0, 1);
+        writer.blink(writer.last_output);
+        assert_eq!(writer.cursor_drawn, None);
+        writer.blink(writer.last_output + 1);
+        assert_eq!(writer.cursor_drawn, Some((0, 1)));
+        assert_ne!(cell_pixels(&writer, 0, 1), blank);
+
+        writer.arrow_left();
+        assert_eq!(writer.cursor_drawn, None);
+        assert_eq!(cell_pixels(&writer, 0, 1), blank);
+        writer.blink(writer.last_output + 1);
+        assert_eq!(writer.cursor_drawn, Some((0, 0)));
+    }
+
+    #[test_case]
+    fn cursor_blinks_and_waits_while_busy() {
+        let mut writer = test_writer();
+        let interval = time::duration_to_ticks(CURSOR_BLINK_INTERVAL);
+        writer.blink(writer.last_output + 1 + interval);
+        assert_eq!(writer.cursor_drawn, None);
+        writer.busy.store(true, Ordering::Relaxed);
+        writer.blink(writer.last_output + 1);
+        assert_eq!(writer.cursor_drawn, None);
+        writer.busy.store(false, Ordering::Relaxed);
+        writer.blink(writer.last_output + 1);
+        assert_eq!(writer.cursor_drawn, Some((0, 0)));
+    }
+
+    #[test_case]
+    fn drawing_hides_the_cursor_and_is_flushed() {
+        let mut writer = test_writer();
+        writer.blink(writer.last_output + 1);
+        assert_eq!(writer.cursor_drawn, Some((0, 0)));
+        let right = writer.draw(|canvas| {
+            canvas.fill_rect(0, 0, 4, 4, [255, 255, 255, 255]);
+            canvas.width() - 1
+        });
+        assert_eq!(right, WIDTH - 1);
+        assert_eq!(writer.cursor_drawn, None);
+        assert!(!writer.busy.load(Ordering::Relaxed));
+        assert_eq!(writer.framebuffer[..4], [255, 255, 255, 0]);
+    }
+
+    #[test_case]
+    fn cursor_shape_and_visibility_escapes() {
+        let mut writer = test_writer();
+        write!(writer, "\x1b[5 q").unwrap();
+        assert_eq!(writer.cursor_shape, CursorShape::Bar);
+        write!(writer, "\x1b[?25l").unwrap();
+        writer.blink(writer.last_output + 1);
+        assert_eq!(writer.cursor_drawn, None);
+        write!(writer, "\x1b[?25h").unwrap();
+        writer.blink(writer.last_output + 1);
+        assert_eq!(writer.cursor_drawn, Some((0, 0)));
+        assert!(cell_pixels(&writer, 0, 0)[..4]
+            .iter()
+            .any(|byte| *byte != 0));
+    }
+
+    #[test_case]
+    fn font_changes_resize_the_grid() {
+        let mut writer = test_writer();
+        for i in 0..3 {
+            write!(writer, "{}", i).unwrap();
+            if i < 2 {
+                writeln!(writer).unwrap();
+            }
+        }
+        let (rows, columns) = writer.size();
+
+        //8x30 glyphs in 8x32 cells leave room for one row
+        let mut data = Vec::from([0x36, 0x04, 0, 30]);
+        data.resize(4 + 256 * 30, 0xff);
+        let font = PsfFont::parse(data.leak()).unwrap();
+        assert!(writer.set_font(Font::Psf(font)));
+        assert_eq!((writer.char_width, writer.line_height), (8, 32));
+        assert_eq!(writer.size(), (1, (WIDTH - 2 * BORDER_PADDING) / 8));
+        assert_eq!(row_text(writer.grid.row(0)), "2");
+        assert_eq!(writer.cursor(), (0, 1));
+        assert!(writer.scrollback.scroll_view(isize::MAX));
+        assert_eq!(row_text(writer.scrollback.view_line(0, &writer.grid)), "0");
+        writer.scrollback.reset_view();
+
+        //too tall for the screen, nothing changes
+        let mut data = Vec::from([0x36, 0x04, 0, 64]);
+        data.resize(4 + 256 * 64, 0);
+        assert!(!writer.set_font(Font::Psf(PsfFont::parse(data.leak()).unwrap())));
+        assert_eq!(writer.size().0, 1);
+        assert!(writer.set_font(Font::Noto {
+            weight: FONT_WEIGHT,
+            size: CHAR_RASTER_HEIGHT
+        }));
+        assert_eq!(writer.size(), (rows, columns));
+    }
+
+    #[test_case]
+    fn backspace_at_origin_does_nothing() {
+        let mut writer = test_writer();
+        writer.backspace();
+        assert_eq!(writer.cursor(), (0, 0));
+    }
+}
@@ -0,0 +1,256 @@
+//VT100/ANSI escape sequence parser. It only splits the input into printable
+//characters and control sequences, FrameBufferWriter decides what they do.
+
+const MAX_PARAMS: usize = 16;
+
+const ESC: char = '\u{1b}';
+const BEL: char = '\u{07}';
+//Both abort a sequence in progress.
+const CAN: char = '\u{18}';
+const SUB: char = '\u{1a}';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    //A character to draw, or a control character like '\n'.
+    Print(char),
+    //ESC followed by `char`, e.g. ESC 7 saves the cursor.
+    Escape(char),
+    //ESC [ params final
+    Csi(Csi),
+}
+
+//A control sequence: `ESC [ 1 ; 2 H` has the parameters [1, 2] and the final
+//character 'H'.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Csi {
+    params: [u16; MAX_PARAMS],
+    len: usize,
+    //Set by a leading '?', e.g. ESC [ ? 25 l.
+    pub private: bool,
+    //The character between parameters and final one, e.g. ' ' in
+    //ESC [ 2 SP q; '\0' if there is none.
+    pub intermediate: char,
+    pub final_char: char,
+}
+
+impl Csi {
+    pub fn params(&self) -> &[u16] {
+        &self.params[..self.len]
+    }
+
+    //Parameter `index`, missing or 0 meaning `default`.
+    pub fn param(&self, index: usize, default: u16) -> u16 {
+        match self.params().get(index) {
+            Some(0) | None => default,
+            Some(&value) => value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    Csi,
+    //Operating system command, ESC ] ... BEL, skipped.
+    Osc,
+    OscEscape,
+}
+
+pub struct Parser {
+    state: State,
+    csi: Csi,
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Parser {
+            state: State::Ground,
+            csi: Csi {
+                params: [0; MAX_PARAMS],
+                len: 0,
+                private: false,
+                intermediate: '\0',
+                final_char: '\0',
+            },
+        }
+    }
+
+    //Feed one character, returns what to do once something is complete.
+    pub fn advance(&mut self, c: char) -> Option<Action> {
+        match (self.state, c) {
+            (_, CAN | SUB) => {
+                self.state = State::Ground;
+                None
+            }
+            (State::Osc, ESC) => {
+                self.state = State::OscEscape;
+                None
+            }
+            (State::Osc, BEL) | (State::OscEscape, '\\') => {
+                self.state = State::Ground;
+                None
+            }
+            (State::Osc, _) => None,
+            (_, ESC) => {
+                self.state = State::Escape;
+                None
+            }
+            (State::Ground, c) => Some(Action::Print(c)),
+            (State::Escape, '[') => {
+                self.state = State::Csi;
+                self.csi.params = [0; MAX_PARAMS];
+                self.csi.len = 0;
+                self.csi.private = false;
+                self.csi.intermediate = '\0';
+                None
+            }
+            (State::Escape, ']') => {
+                self.state = State::Osc;
+                None
+            }
+            //ESC inside a command that is not its end starts a new sequence.
+            (State::OscEscape, c) => {
+                self.state = State::Escape;
+                self.advance(c)
+            }
+            (State::Escape, c) => {
+                self.state = State::Ground;
+                Some(Action::Escape(c))
+            }
+            (State::Csi, c) => self.csi_char(c),
+        }
+    }
+
+    fn csi_char(&mut self, c: char) -> Option<Action> {
+        let csi = &mut self.csi;
+        match c {
+            '0'..='9' => {
+                if csi.len == 0 {
+                    csi.len = 1;
+                }
+                if let Some(param) = csi.params.get_mut(csi.len - 1) {
+                    let digit = c as u16 - '0' as u16;
+                    *param = param.saturating_mul(10).saturating_add(digit);
+                }
+            }
+            ';' | ':' => {
+                if csi.len == 0 {
+                    csi.len = 1;
+                }
+                csi.len = (csi.len + 1).min(MAX_PARAMS);
+            }
+            '?' => csi.private = true,
+            ' '..='/' => csi.intermediate = c,
+            '<'..='>' => {}
+            '@'..='~' => {
+                csi.final_char = c;
+                self.state = State::Ground;
+                return Some(Action::Csi(*csi));
+            }
+            //Controls inside a sequence still take effect.
+            c if c.is_control() => return Some(Action::Print(c)),
+            _ => self.state = State::Ground,
+        }
+        None
+    }
+}
+
+//Colors of the 256 color palette, the first 16 are the classic ones.
+pub fn palette(index: u8) -> [u8; 4] {
+    const BASIC: [[u8; 3]; 16] = [
+        [0, 0, 0],
+        [205, 49, 49],
+        [13, 188, 121],
+        [229, 229, 16],
+        [36, 114, 200],
+        [188, 63, 188],
+        [17, 168, 205],
+        [229, 229, 229],
+        [102, 102, 102],
+        [241, 76, 76],
+        [35, 209, 139],
+        [245, 245, 67],
+        [59, 142, 234],
+        [214, 112, 214],
+        [41, 184, 219],
+        [255, 255, 255],
+    ];
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    let [r, g, b] = match index {
+        0..=15 => BASIC[index as usize],
+        16..=231 => {
+            let cube = index as usize - 16;
+            [LEVELS[cube / 36], LEVELS[cube / 6 % 6], LEVELS[cube % 6]]
+        }
+        232..=255 => {
+            let gray = 8 + 10 * (index - 232);
+            [gray, gray, gray]
+        }
+    };
+    [r, g, b, 255]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn parse(input: &str) -> Vec<Action> {
+        let mut parser = Parser::new();
+        input.chars().filter_map(|c| parser.advance(c)).collect()
+    }
+
+    #[test_case]
+    fn plain_text_is_printed() {
+        assert_eq!(parse("a\n"), [Action::Print('a'), Action::Print('\n')]);
+    }
+
+    #[test_case]
+    fn csi_parameters() {
+        match parse("\u{1b}[12;;3H")[..] {
+            [Action::Csi(csi)] => {
+                assert_eq!(csi.final_char, 'H');
+                assert_eq!(csi.params(), [12, 0, 3]);
+                assert_eq!(csi.param(1, 1), 1);
+                assert_eq!(csi.param(5, 7), 7);
+            }
+            ref other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test_case]
+    fn intermediate_characters() {
+        match parse("\u{1b}[4 q")[..] {
+            [Action::Csi(csi)] => {
+                assert_eq!((csi.intermediate, csi.final_char), (' ', 'q'));
+                assert_eq!(csi.params(), [4]);
+            }
+            ref other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test_case]
+    fn private_and_escape_sequences() {
+        match parse("\u{1b}[?25l\u{1b}7")[..] {
+            [Action::Csi(csi), Action::Escape('7')] => assert!(csi.private),
+            ref other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test_case]
+    fn osc_and_cancelled_sequences_are_dropped() {
+        assert_eq!(
+            parse("\u{1b}]0;title\u{07}\u{1b}[3\u{18}x"),
+            [Action::Print('x')]
+        );
+    }
+
+    #[test_case]
+    fn palette_colors() {
+        assert_eq!(palette(1), [205, 49, 49, 255]);
+        assert_eq!(palette(16), [0, 0, 0, 255]);
+        assert_eq!(palette(231), [255, 255, 255, 255]);
+        assert_eq!(palette(232), [8, 8, 8, 255]);
+    }
+}
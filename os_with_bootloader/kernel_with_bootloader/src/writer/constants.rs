@@ -1,9 +1,11 @@
-use noto_sans_mono_bitmap::{get_raster_width, FontWeight, RasterHeight};
+use noto_sans_mono_bitmap::{FontWeight, RasterHeight};
 
+//The font at boot, it can be changed with FrameBufferWriter::set_font.
 pub mod font_constants {
     use super::*;
     pub const CHAR_RASTER_HEIGHT: RasterHeight = RasterHeight::Size16;
-    pub const CHAR_RASTER_WIDTH: usize = get_raster_width(FontWeight::Regular, CHAR_RASTER_HEIGHT);
     pub const BACKUP_CHAR: char = '�';
     pub const FONT_WEIGHT: FontWeight = FontWeight::Regular;
+    //for bold text, same width as regular
+    pub const BOLD_FONT_WEIGHT: FontWeight = FontWeight::Bold;
 }
@@ -0,0 +1,57 @@
+//Shape of the text cursor and its blinking.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorShape {
+    //The cell drawn in reverse.
+    Block,
+    Underline,
+    //A thin line left of the cell.
+    Bar,
+}
+
+impl CursorShape {
+    //The shapes of DECSCUSR, ESC [ n SP q. We always blink, so the steady
+    //variants map to the same shape.
+    pub fn from_decscusr(style: u16) -> Option<CursorShape> {
+        match style {
+            0..=2 => Some(CursorShape::Block),
+            3 | 4 => Some(CursorShape::Underline),
+            5 | 6 => Some(CursorShape::Bar),
+            _ => None,
+        }
+    }
+}
+
+//Whether the cursor is shown at tick `now` when the last output was at
+//`last_output`. It stays off while output keeps coming, shows up on the next
+//tick without any and then turns off and on every `interval` ticks.
+pub fn blink_on(now: u64, last_output: u64, interval: u64) -> bool {
+    match now.checked_sub(last_output) {
+        None | Some(0) => false,
+        Some(quiet) => (quiet - 1) / interval.max(1) % 2 == 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn cursor_blinks_after_output() {
+        assert!(!blink_on(10, 10, 50));
+        assert!(blink_on(11, 10, 50));
+        assert!(blink_on(60, 10, 50));
+        assert!(!blink_on(61, 10, 50));
+        assert!(blink_on(111, 10, 50));
+        //output from an interrupt after `now` was read
+        assert!(!blink_on(9, 10, 50));
+    }
+
+    #[test_case]
+    fn decscusr_shapes() {
+        assert_eq!(CursorShape::from_decscusr(0), Some(CursorShape::Block));
+        assert_eq!(CursorShape::from_decscusr(4), Some(CursorShape::Underline));
+        assert_eq!(CursorShape::from_decscusr(5), Some(CursorShape::Bar));
+        assert_eq!(CursorShape::from_decscusr(7), None);
+    }
+}
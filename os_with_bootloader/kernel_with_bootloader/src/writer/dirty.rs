@@ -0,0 +1,131 @@
+//Parts of the back buffer that changed since the last flush.
+
+const MAX_RECTS: usize = 8;
+
+//In pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub const fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn right(&self) -> usize {
+        self.x + self.width
+    }
+
+    pub fn bottom(&self) -> usize {
+        self.y + self.height
+    }
+
+    fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    //Overlapping or sharing an edge.
+    fn touches(&self, other: &Rect) -> bool {
+        self.x <= other.right()
+            && other.x <= self.right()
+            && self.y <= other.bottom()
+            && other.y <= self.bottom()
+    }
+
+    fn union(&self, other: &Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect::new(
+            x,
+            y,
+            self.right().max(other.right()) - x,
+            self.bottom().max(other.bottom()) - y,
+        )
+    }
+}
+
+//A few rectangles; touching ones are merged, and when there are too many they
+//become their bounding box. Fixed size, it is updated from interrupt handlers.
+pub struct DirtyRegion {
+    rects: [Rect; MAX_RECTS],
+    len: usize,
+}
+
+impl DirtyRegion {
+    pub const fn new() -> Self {
+        DirtyRegion {
+            rects: [Rect::new(0, 0, 0, 0); MAX_RECTS],
+            len: 0,
+        }
+    }
+
+    pub fn add(&mut self, rect: Rect) {
+        if rect.is_empty() {
+            return;
+        }
+        let mut rect = rect;
+        //A merged rectangle can touch others that the original did not.
+        while let Some(index) = self.rects().iter().position(|dirty| dirty.touches(&rect)) {
+            rect = rect.union(&self.rects[index]);
+            self.len -= 1;
+            self.rects[index] = self.rects[self.len];
+        }
+        if self.len == MAX_RECTS {
+            rect = self
+                .rects()
+                .iter()
+                .fold(rect, |all, dirty| all.union(dirty));
+            self.len = 0;
+        }
+        self.rects[self.len] = rect;
+        self.len += 1;
+    }
+
+    pub fn rects(&self) -> &[Rect] {
+        &self.rects[..self.len]
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn touching_rects_are_merged() {
+        let mut dirty = DirtyRegion::new();
+        dirty.add(Rect::new(0, 0, 8, 16));
+        dirty.add(Rect::new(8, 0, 8, 16));
+        dirty.add(Rect::new(100, 100, 8, 16));
+        dirty.add(Rect::new(0, 0, 0, 16));
+        assert_eq!(
+            dirty.rects(),
+            [Rect::new(0, 0, 16, 16), Rect::new(100, 100, 8, 16)]
+        );
+        //joins both
+        dirty.add(Rect::new(10, 10, 95, 95));
+        assert_eq!(dirty.rects(), [Rect::new(0, 0, 108, 116)]);
+    }
+
+    #[test_case]
+    fn too_many_rects_become_one() {
+        let mut dirty = DirtyRegion::new();
+        for i in 0..=MAX_RECTS {
+            dirty.add(Rect::new(i * 10, i * 10, 1, 1));
+        }
+        let last = MAX_RECTS * 10;
+        assert_eq!(dirty.rects(), [Rect::new(0, 0, last + 1, last + 1)]);
+    }
+}
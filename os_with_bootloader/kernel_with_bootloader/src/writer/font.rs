@@ -0,0 +1,96 @@
+//Fonts the writer can draw with: the built-in noto_sans_mono_bitmap in any
+//compiled-in weight and size, or a PC Screen Font.
+mod psf;
+
+pub use psf::{PsfError, PsfFont};
+
+use super::constants::font_constants::{BACKUP_CHAR, BOLD_FONT_WEIGHT};
+use core::fmt;
+use noto_sans_mono_bitmap::{
+    get_raster, get_raster_width, FontWeight, RasterHeight, RasterizedChar,
+};
+
+pub enum Font {
+    Noto {
+        weight: FontWeight,
+        size: RasterHeight,
+    },
+    Psf(PsfFont),
+}
+
+impl Font {
+    //Size of a glyph in pixels, as (width, height).
+    pub fn glyph_size(&self) -> (usize, usize) {
+        match self {
+            Font::Noto { weight, size } => (get_raster_width(*weight, *size), size.val()),
+            Font::Psf(font) => (font.width(), font.height()),
+        }
+    }
+
+    pub fn glyph(&self, c: char, bold: bool) -> Glyph<'_> {
+        match self {
+            Font::Noto { weight, size } => {
+                let weight = if bold { BOLD_FONT_WEIGHT } else { *weight };
+                let get = |c| get_raster(c, weight, *size);
+                Glyph::Noto(get(c).unwrap_or_else(|| {
+                    get(BACKUP_CHAR).expect("Should get raster of backup char.")
+                }))
+            }
+            Font::Psf(font) => Glyph::Psf {
+                font,
+                bitmap: font.glyph(c),
+                bold,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Font {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (width, height) = self.glyph_size();
+        match self {
+            Font::Noto { weight, size } => {
+                let weight = match weight {
+                    FontWeight::Light => "light",
+                    FontWeight::Regular => "regular",
+                    FontWeight::Bold => "bold",
+                };
+                write!(f, "noto {} {}", weight, size.val())?;
+            }
+            Font::Psf(font) => write!(f, "psf, {} glyphs", font.glyph_count())?,
+        }
+        write!(f, " ({}x{} pixels)", width, height)
+    }
+}
+
+pub enum Glyph<'a> {
+    Noto(RasterizedChar),
+    Psf {
+        font: &'a PsfFont,
+        bitmap: &'a [u8],
+        //PSF fonts have one weight, bold is drawn by thickening it.
+        bold: bool,
+    },
+}
+
+impl Glyph<'_> {
+    //How much the glyph covers pixel (x, y), 0 outside of it.
+    pub fn intensity(&self, x: usize, y: usize) -> u8 {
+        match self {
+            Glyph::Noto(raster) => raster
+                .raster()
+                .get(y)
+                .and_then(|row| row.get(x))
+                .copied()
+                .unwrap_or(0),
+            Glyph::Psf { font, bitmap, bold } => {
+                let set =
+                    font.pixel(bitmap, x, y) || (*bold && x > 0 && font.pixel(bitmap, x - 1, y));
+                match set {
+                    true => 255,
+                    false => 0,
+                }
+            }
+        }
+    }
+}
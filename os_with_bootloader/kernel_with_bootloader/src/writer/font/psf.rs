@@ -0,0 +1,280 @@
+//PC Screen Font, versions 1 and 2, as used by the Linux console.
+use alloc::collections::BTreeMap;
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_MODE_HAS_TABLE: u8 = 0x02;
+const PSF1_HEADER_SIZE: usize = 4;
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+const PSF2_HAS_UNICODE_TABLE: u32 = 0x01;
+const PSF2_HEADER_SIZE: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PsfError {
+    BadMagic,
+    UnsupportedVersion(u32),
+    //The header describes more data than there is.
+    Truncated,
+    //No glyphs, or a glyph of size 0.
+    Empty,
+}
+
+pub struct PsfFont {
+    glyphs: &'static [u8],
+    glyph_count: usize,
+    bytes_per_glyph: usize,
+    width: usize,
+    height: usize,
+    //From the font's unicode table. Without one, glyphs are indexed by code
+    //point.
+    unicode: BTreeMap<char, usize>,
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32, PsfError> {
+    match data.get(offset..offset + 4) {
+        Some(bytes) => Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+        None => Err(PsfError::Truncated),
+    }
+}
+
+impl PsfFont {
+    //The font in `data`, e.g. an include_bytes!() or a file on the ramdisk.
+    pub fn parse(data: &'static [u8]) -> Result<PsfFont, PsfError> {
+        if data.starts_with(&PSF2_MAGIC) {
+            Self::parse_psf2(data)
+        } else if data.starts_with(&PSF1_MAGIC) {
+            Self::parse_psf1(data)
+        } else {
+            Err(PsfError::BadMagic)
+        }
+    }
+
+    fn parse_psf1(data: &'static [u8]) -> Result<PsfFont, PsfError> {
+        let mode = *data.get(2).ok_or(PsfError::Truncated)?;
+        let height = *data.get(3).ok_or(PsfError::Truncated)? as usize;
+        let glyph_count = match mode & PSF1_MODE_512 {
+            0 => 256,
+            _ => 512,
+        };
+        let mut font = Self::new(data, PSF1_HEADER_SIZE, glyph_count, height, 8, height)?;
+        if mode & PSF1_MODE_HAS_TABLE != 0 {
+            let table = &data[PSF1_HEADER_SIZE + glyph_count * height..];
+            let entries = table
+                .chunks_exact(2)
+                .map(|pair| u16::from_le_bytes([pair[0], pair[1]]));
+            let mut glyph = 0;
+            let mut sequence = false;
+            for entry in entries {
+                //entries past the last glyph have nothing to point at
+                if glyph == glyph_count {
+                    break;
+                }
+                match entry {
+                    0xffff => {
+                        glyph += 1;
+                        sequence = false;
+                    }
+                    //combining sequences follow, they are not supported
+                    0xfffe => sequence = true,
+                    _ if sequence => {}
+                    code => {
+                        if let Some(c) = char::from_u32(code as u32) {
+                            font.unicode.entry(c).or_insert(glyph);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(font)
+    }
+
+    fn parse_psf2(data: &'static [u8]) -> Result<PsfFont, PsfError> {
+        let version = u32_at(data, 4)?;
+        if version != 0 {
+            return Err(PsfError::UnsupportedVersion(version));
+        }
+        let header_size = u32_at(data, 8)? as usize;
+        let flags = u32_at(data, 12)?;
+        let glyph_count = u32_at(data, 16)? as usize;
+        let bytes_per_glyph = u32_at(data, 20)? as usize;
+        let height = u32_at(data, 24)? as usize;
+        let width = u32_at(data, 28)? as usize;
+        if header_size < PSF2_HEADER_SIZE {
+            return Err(PsfError::Truncated);
+        }
+        let mut font = Self::new(
+            data,
+            header_size,
+            glyph_count,
+            bytes_per_glyph,
+            width,
+            height,
+        )?;
+        if flags & PSF2_HAS_UNICODE_TABLE != 0 {
+            let table = &data[header_size + glyph_count * bytes_per_glyph..];
+            //One entry per glyph, ended by 0xff. 0xfe starts sequences.
+            let entries = table.split(|byte| *byte == 0xff).take(glyph_count);
+            for (glyph, entry) in entries.enumerate() {
+                let single = entry.split(|byte| *byte == 0xfe).next().unwrap_or(&[]);
+                if let Ok(text) = core::str::from_utf8(single) {
+                    for c in text.chars() {
+                        font.unicode.entry(c).or_insert(glyph);
+                    }
+                }
+            }
+        }
+        Ok(font)
+    }
+
+    fn new(
+        data: &'static [u8],
+        offset: usize,
+        glyph_count: usize,
+        bytes_per_glyph: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<PsfFont, PsfError> {
+        if glyph_count == 0 || width == 0 || height == 0 {
+            return Err(PsfError::Empty);
+        }
+        if bytes_per_glyph < (width + 7) / 8 * height {
+            return Err(PsfError::Truncated);
+        }
+        let glyphs = glyph_count
+            .checked_mul(bytes_per_glyph)
+            .and_then(|size| data.get(offset..offset.checked_add(size)?))
+            .ok_or(PsfError::Truncated)?;
+        Ok(PsfFont {
+            glyphs,
+            glyph_count,
+            bytes_per_glyph,
+            width,
+            height,
+            unicode: BTreeMap::new(),
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn glyph_count(&self) -> usize {
+        self.glyph_count
+    }
+
+    fn index(&self, c: char) -> Option<usize> {
+        match self.unicode.is_empty() {
+            true => Some(c as usize).filter(|index| *index < self.glyph_count),
+            false => self.unicode.get(&c).copied(),
+        }
+    }
+
+    //Bitmap of the glyph for `c`, '?' or the first glyph if there is none.
+    pub fn glyph(&self, c: char) -> &'static [u8] {
+        let index = self.index(c).or_else(|| self.index('?')).unwrap_or(0);
+        let start = index.saturating_mul(self.bytes_per_glyph);
+        let first = &self.glyphs[..self.bytes_per_glyph];
+        self.glyphs
+            .get(start..start.saturating_add(self.bytes_per_glyph))
+            .unwrap_or(first)
+    }
+
+    //Whether pixel (x, y) of a glyph bitmap is set. Rows are padded to whole
+    //bytes, the leftmost pixel is the highest bit.
+    pub fn pixel(&self, bitmap: &[u8], x: usize, y: usize) -> bool {
+        if x >= self.width || y >= self.height {
+            return false;
+        }
+        let bytes_per_row = (self.width + 7) / 8;
+        bitmap[y * bytes_per_row + x / 8] & (0x80 >> (x % 8)) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    //A PSF2 font of 10x2 glyphs: 'a' is the top row, 'b' the left column.
+    fn psf2_font() -> &'static [u8] {
+        let mut data = Vec::new();
+        data.extend_from_slice(&PSF2_MAGIC);
+        for value in [0, 32, PSF2_HAS_UNICODE_TABLE, 2, 4, 2, 10] {
+            data.extend_from_slice(&u32::to_le_bytes(value));
+        }
+        data.extend_from_slice(&[0xff, 0xc0, 0x00, 0x00]);
+        data.extend_from_slice(&[0x80, 0x00, 0x80, 0x00]);
+        data.extend_from_slice(b"aA\xfe\xcc\xff");
+        data.extend_from_slice(b"b\xff");
+        data.leak()
+    }
+
+    #[test_case]
+    fn psf2_glyphs_and_unicode_table() {
+        let font = PsfFont::parse(psf2_font()).unwrap();
+        assert_eq!(
+            (font.width(), font.height(), font.glyph_count()),
+            (10, 2, 2)
+        );
+        let a = font.glyph('A');
+        assert!((0..10).all(|x| font.pixel(a, x, 0)));
+        assert!(!font.pixel(a, 0, 1) && !font.pixel(a, 10, 0));
+        let b = font.glyph('b');
+        assert!(font.pixel(b, 0, 1) && !font.pixel(b, 1, 1));
+        //unknown characters fall back to the first glyph
+        assert_eq!(font.glyph('z'), a);
+    }
+
+    #[test_case]
+    fn psf1_is_indexed_by_code_point() {
+        let mut data = Vec::from([0x36, 0x04, 0, 1]);
+        data.extend((0..256).map(|index| index as u8));
+        let font = PsfFont::parse(data.leak()).unwrap();
+        assert_eq!(
+            (font.width(), font.height(), font.glyph_count()),
+            (8, 1, 256)
+        );
+        assert_eq!(font.glyph('A'), [b'A']);
+        assert_eq!(font.glyph('\u{263a}'), [b'?']);
+    }
+
+    #[test_case]
+    fn unicode_entries_past_the_last_glyph_are_ignored() {
+        let mut data = Vec::from(psf2_font());
+        data.extend_from_slice(b"c\xffd\xff");
+        let font = PsfFont::parse(data.leak()).unwrap();
+        assert_eq!(font.glyph_count(), 2);
+        assert_eq!(font.index('c'), None);
+        assert_eq!(font.glyph('c'), font.glyph('a'));
+
+        let mut data = Vec::from([0x36, 0x04, PSF1_MODE_HAS_TABLE, 1]);
+        data.extend((0..256).map(|index| index as u8));
+        for code in 0..=256u16 {
+            data.extend_from_slice(&code.to_le_bytes());
+            data.extend_from_slice(&[0xff, 0xff]);
+        }
+        let font = PsfFont::parse(data.leak()).unwrap();
+        assert_eq!(font.glyph('\u{ff}'), [0xff]);
+        assert_eq!(font.index('\u{100}'), None);
+        assert_eq!(font.glyph('\u{100}'), [b'?']);
+    }
+
+    #[test_case]
+    fn broken_fonts_are_rejected() {
+        assert_eq!(PsfFont::parse(b"hello").err(), Some(PsfError::BadMagic));
+        assert_eq!(
+            PsfFont::parse(&[0x36, 0x04, 0, 16, 0]).err(),
+            Some(PsfError::Truncated)
+        );
+        let mut data = Vec::from(psf2_font());
+        data[4] = 1;
+        assert_eq!(
+            PsfFont::parse(data.leak()).err(),
+            Some(PsfError::UnsupportedVersion(1))
+        );
+    }
+}
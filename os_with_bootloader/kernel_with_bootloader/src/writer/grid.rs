@@ -0,0 +1,129 @@
+use alloc::vec::Vec;
+use core::ops::Range;
+
+//How a cell is drawn. Colors are [r, g, b, a].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Style {
+    pub foreground: [u8; 4],
+    pub background: [u8; 4],
+    pub bold: bool,
+    //Draw with foreground and background swapped.
+    pub reverse: bool,
+}
+
+impl Style {
+    //The colors to draw with, as (foreground, background).
+    pub fn colors(&self) -> ([u8; 4], [u8; 4]) {
+        match self.reverse {
+            false => (self.foreground, self.background),
+            true => (self.background, self.foreground),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub character: char,
+    pub style: Style,
+}
+
+impl Cell {
+    pub const fn blank(style: Style) -> Cell {
+        Cell {
+            character: ' ',
+            style,
+        }
+    }
+}
+
+//The text on screen, rows by columns. The framebuffer is always drawn from
+//this, so the screen can be redrawn and read back.
+pub struct Grid {
+    rows: usize,
+    columns: usize,
+    cells: Vec<Cell>,
+}
+
+impl Grid {
+    pub fn new(rows: usize, columns: usize, blank: Cell) -> Self {
+        let mut cells = Vec::new();
+        cells.resize(rows * columns, blank);
+        Grid {
+            rows,
+            columns,
+            cells,
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    pub fn get(&self, row: usize, column: usize) -> Option<Cell> {
+        self.row(row).get(column).copied()
+    }
+
+    pub fn set(&mut self, row: usize, column: usize, cell: Cell) {
+        if let Some(slot) = self.row_mut(row).get_mut(column) {
+            *slot = cell;
+        }
+    }
+
+    pub fn row(&self, row: usize) -> &[Cell] {
+        match row < self.rows {
+            true => &self.cells[row * self.columns..(row + 1) * self.columns],
+            false => &[],
+        }
+    }
+
+    fn row_mut(&mut self, row: usize) -> &mut [Cell] {
+        match row < self.rows {
+            true => &mut self.cells[row * self.columns..(row + 1) * self.columns],
+            false => &mut [],
+        }
+    }
+
+    pub fn clear(&mut self, blank: Cell) {
+        self.cells.fill(blank);
+    }
+
+    //Blank `columns` of `row`.
+    pub fn clear_columns(&mut self, row: usize, columns: Range<usize>, blank: Cell) {
+        let cells = self.row_mut(row);
+        let end = columns.end.min(cells.len());
+        if columns.start < end {
+            cells[columns.start..end].fill(blank);
+        }
+    }
+
+    //Drop the top row, everything moves up and the bottom row is blank.
+    pub fn scroll_up(&mut self, blank: Cell) {
+        self.cells.copy_within(self.columns.., 0);
+        let last = self.rows - 1;
+        self.row_mut(last).fill(blank);
+    }
+
+    //Put `cell` at `column`, moving the rest of the row right. The last cell
+    //of the row falls off.
+    pub fn insert(&mut self, row: usize, column: usize, cell: Cell) {
+        let cells = self.row_mut(row);
+        if column < cells.len() {
+            cells[column..].rotate_right(1);
+            cells[column] = cell;
+        }
+    }
+
+    //Remove the cell at `column`, moving the rest of the row left.
+    pub fn delete(&mut self, row: usize, column: usize, blank: Cell) {
+        let cells = self.row_mut(row);
+        if column < cells.len() {
+            cells[column..].rotate_left(1);
+            let last = cells.len() - 1;
+            cells[last] = blank;
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use super::grid::{Cell, Grid};
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+//The rows that scrolled off the top of the grid, so they can be drawn again
+//when the user scrolls back. Everything is allocated up front: the writer
+//runs in interrupt handlers, where allocating could deadlock on the heap lock.
+pub struct Scrollback {
+    columns: usize,
+    history: VecDeque<Vec<Cell>>,
+    //Empty lines, taken for new history until it is full.
+    spare: Vec<Vec<Cell>>,
+    //How many lines the view is scrolled back, 0 is the live screen.
+    offset: usize,
+}
+
+impl Scrollback {
+    pub fn new(columns: usize, capacity: usize) -> Self {
+        Scrollback {
+            columns,
+            history: VecDeque::with_capacity(capacity),
+            spare: (0..capacity).map(|_| Vec::with_capacity(columns)).collect(),
+            offset: 0,
+        }
+    }
+
+    //Keep `row`, which is leaving the screen.
+    pub fn push(&mut self, row: &[Cell]) {
+        let mut line = match self.spare.pop() {
+            Some(line) => line,
+            None => match self.history.pop_front() {
+                Some(line) => line,
+                None => return,
+            },
+        };
+        line.clear();
+        line.extend_from_slice(&row[..row.len().min(self.columns)]);
+        self.history.push_back(line);
+    }
+
+    //For a new grid width. Longer lines are cut, shorter ones stay short.
+    pub fn set_columns(&mut self, columns: usize) {
+        self.columns = columns;
+        for line in self.history.iter_mut() {
+            line.truncate(columns);
+        }
+        for line in self.history.iter_mut().chain(self.spare.iter_mut()) {
+            line.reserve_exact(columns.saturating_sub(line.len()));
+        }
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    //Scroll the view by `lines`, positive is back in time. Returns true if
+    //the view moved.
+    pub fn scroll_view(&mut self, lines: isize) -> bool {
+        let offset = self
+            .offset
+            .saturating_add_signed(lines)
+            .min(self.history.len());
+        let moved = offset != self.offset;
+        self.offset = offset;
+        moved
+    }
+
+    pub fn reset_view(&mut self) {
+        self.offset = 0;
+    }
+
+    //Cells of screen row `row` in the current view, `grid` being the live
+    //screen. Lines from before a font change can be shorter than the grid.
+    pub fn view_line<'a>(&'a self, row: usize, grid: &'a Grid) -> &'a [Cell] {
+        let line = self.history.len() - self.offset + row;
+        match line.checked_sub(self.history.len()) {
+            None => &self.history[line],
+            Some(row) => grid.row(row),
+        }
+    }
+}
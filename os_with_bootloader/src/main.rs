@@ -1,13 +1,87 @@
-fn main() {
+use std::process::{Command, ExitStatus};
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "\
+Boot the kernel in QEMU.
+
+Usage: os_with_bootloader [OPTIONS] [-- QEMU_ARGS...]
+
+Options:
+  --bios               boot the BIOS image (default)
+  --uefi               boot the UEFI image
+  --headless           no window, QEMU's console on the terminal (-nographic)
+  -m, --memory SIZE    guest memory, e.g. 512M or 2G (QEMU's default if unset)
+  --cpus N             number of CPUs
+  --serial-log FILE    write the kernel's serial output to FILE instead of the terminal
+  --timeout SECONDS    kill QEMU after SECONDS and exit with status 124
+  --gdb                start a GDB stub on tcp::1234 and wait for it (-s -S)
+  -h, --help           print this help
+
+Everything after `--` is passed to QEMU unchanged. The exit status is QEMU's.";
+
+// exit status on --timeout, same as timeout(1)
+const TIMEOUT_EXIT_CODE: i32 = 124;
+
+#[derive(Debug, Default)]
+struct Options {
+    uefi: bool,
+    headless: bool,
+    memory: Option<String>,
+    cpus: Option<u32>,
+    serial_log: Option<String>,
+    timeout: Option<Duration>,
+    gdb: bool,
+    qemu_args: Vec<String>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{name} needs a value"));
+        match arg.as_str() {
+            "--bios" => options.uefi = false,
+            "--uefi" => options.uefi = true,
+            "--headless" => options.headless = true,
+            "-m" | "--memory" => options.memory = Some(value(&arg)?),
+            "--cpus" => {
+                let cpus = value(&arg)?;
+                match cpus.parse() {
+                    Ok(cpus) if cpus > 0 => options.cpus = Some(cpus),
+                    _ => return Err(format!("invalid CPU count: {cpus}")),
+                }
+            }
+            "--serial-log" => options.serial_log = Some(value(&arg)?),
+            "--timeout" => {
+                let seconds = value(&arg)?;
+                let seconds = seconds
+                    .parse()
+                    .map_err(|_| format!("invalid timeout: {seconds}"))?;
+                options.timeout = Some(Duration::from_secs(seconds));
+            }
+            "--gdb" => options.gdb = true,
+            "--" => {
+                options.qemu_args.extend(args);
+                break;
+            }
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            other => return Err(format!("unknown option: {other}")),
+        }
+    }
+
+    Ok(options)
+}
+
+fn qemu_command(options: &Options) -> Command {
     // read env variables that were set in build script
     let uefi_path = env!("UEFI_PATH");
     let bios_path = env!("BIOS_PATH");
 
-    // choose whether to start the UEFI or BIOS image
-    let uefi = false;
-
-    let mut cmd = std::process::Command::new("qemu-system-x86_64");
-    if uefi {
+    let mut cmd = Command::new("qemu-system-x86_64");
+    if options.uefi {
         cmd.arg("-bios").arg(ovmf_prebuilt::ovmf_pure_efi());
         cmd.arg("-drive")
             .arg(format!("format=raw,file={uefi_path}"));
@@ -15,6 +89,85 @@ fn main() {
         cmd.arg("-drive")
             .arg(format!("format=raw,file={bios_path}"));
     }
-    let mut child = cmd.spawn().unwrap();
-    child.wait().unwrap();
+
+    if let Some(memory) = &options.memory {
+        cmd.arg("-m").arg(memory);
+    }
+    if let Some(cpus) = options.cpus {
+        cmd.arg("-smp").arg(cpus.to_string());
+    }
+
+    // kernel serial output (COM1) goes to the terminal unless logged to a file;
+    // -nographic already puts it on the terminal
+    match (&options.serial_log, options.headless) {
+        (Some(path), _) => {
+            cmd.arg("-serial").arg(format!("file:{path}"));
+        }
+        (None, false) => {
+            cmd.arg("-serial").arg("stdio");
+        }
+        (None, true) => {}
+    }
+    if options.headless {
+        cmd.arg("-nographic");
+    }
+
+    if options.gdb {
+        cmd.arg("-s").arg("-S");
+    }
+    cmd.args(&options.qemu_args);
+    cmd
+}
+
+// Wait for QEMU, killing it once the timeout has passed. Returns None on timeout.
+fn wait(child: &mut std::process::Child, timeout: Option<Duration>) -> Option<ExitStatus> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return Some(child.wait().unwrap()),
+    };
+
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            return Some(status);
+        }
+        if start.elapsed() >= timeout {
+            child.kill().unwrap();
+            child.wait().unwrap();
+            return None;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+fn main() {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("error: {err}\n\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+
+    let mut cmd = qemu_command(&options);
+    let mut child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(err) => {
+            eprintln!("error: failed to start qemu-system-x86_64: {err}");
+            std::process::exit(1);
+        }
+    };
+    if options.gdb {
+        eprintln!("waiting for GDB on localhost:1234");
+    }
+
+    let code = match wait(&mut child, options.timeout) {
+        // no code means QEMU was killed by a signal
+        Some(status) => status.code().unwrap_or(1),
+        None => {
+            eprintln!("QEMU timed out after {:?}", options.timeout.unwrap());
+            TIMEOUT_EXIT_CODE
+        }
+    };
+    std::process::exit(code);
 }
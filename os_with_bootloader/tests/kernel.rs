@@ -0,0 +1,88 @@
+// Runs the kernel's `#[test_case]` functions: builds the kernel test binary,
+// wraps it in a BIOS image and boots it in QEMU. The kernel reports over the
+// serial port and exits QEMU through the isa-debug-exit device.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+// QEMU exits with (code << 1) | 1, the kernel writes 0x10 on success
+const SUCCESS_EXIT_CODE: i32 = (0x10 << 1) | 1;
+const TIMEOUT: Duration = Duration::from_secs(300);
+
+#[test]
+fn kernel_tests() {
+    let kernel = build_test_kernel();
+
+    let bios_path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("kernel_test_bios.img");
+    bootloader::BiosBoot::new(&kernel)
+        .create_disk_image(&bios_path)
+        .unwrap();
+
+    let mut child = Command::new("qemu-system-x86_64")
+        .arg("-drive")
+        .arg(format!("format=raw,file={}", bios_path.display()))
+        .args(["-device", "isa-debug-exit,iobase=0xf4,iosize=0x04"])
+        .args(["-serial", "stdio", "-display", "none"])
+        .spawn()
+        .expect("failed to start qemu-system-x86_64");
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if start.elapsed() > TIMEOUT {
+            child.kill().unwrap();
+            panic!("kernel tests timed out after {:?}", TIMEOUT);
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    };
+    assert_eq!(
+        status.code(),
+        Some(SUCCESS_EXIT_CODE),
+        "kernel tests failed"
+    );
+}
+
+// `cargo test --no-run` for the kernel. It gets its own target directory so it
+// doesn't wait for the lock held by the `cargo test` running us.
+fn build_test_kernel() -> PathBuf {
+    let kernel_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("kernel_with_bootloader");
+    let output = Command::new(env!("CARGO"))
+        .current_dir(&kernel_dir)
+        .args(["test", "--no-run", "--bin", "kernel_with_bootloader"])
+        .arg("--message-format=json")
+        .arg("--target-dir")
+        .arg(Path::new(env!("CARGO_TARGET_TMPDIR")).join("kernel"))
+        .stderr(Stdio::inherit())
+        .output()
+        .expect("failed to run cargo");
+    assert!(output.status.success(), "building the test kernel failed");
+
+    // one JSON message per line, the test binary is the artifact built with
+    // the test profile
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .filter(|line| line.contains(r#""reason":"compiler-artifact""#))
+        .filter(|line| line.contains(r#""test":true"#))
+        .find_map(|line| json_string(line, "executable"))
+        .map(PathBuf::from)
+        .expect("cargo did not report a test kernel")
+}
+
+// Good enough for cargo's messages, saves pulling in a JSON parser.
+fn json_string(line: &str, key: &str) -> Option<String> {
+    let start = line.find(&format!("\"{key}\":\""))? + key.len() + 4;
+    let mut value = String::new();
+    let mut chars = line[start..].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some(value),
+            '\\' => value.push(chars.next()?),
+            c => value.push(c),
+        }
+    }
+    None
+}